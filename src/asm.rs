@@ -0,0 +1,448 @@
+use {
+    anyhow::{Result, anyhow},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    },
+};
+
+use crate::{errors::AsmError, systems::CHIP8_PC_START};
+
+// Assembler for the dialect printed by `disas`, for listings to be assembled back :
+// - one instruction per line, operands separated by commas and/or spaces, `;` starting a comment
+// - numbers are hexadecimal, optionally prefixed by `0x`, or binary with `0b`, or decimal with `0d`
+// - `name:` defines a label, `name = value` a constant, both usable wherever a number is expected,
+//   possibly added or subtracted, without spaces (e.g. `SETI sprites+0d10`)
+// - `db` and `dw` emit bytes and big endian words, `include "file"` assembles another file in place
+// Names can't be read as hexadecimal numbers or registers, e.g. `add` or `v1` aren't valid labels.
+
+// Nesting limit of includes, and of constants defined from one another
+const MAX_DEPTH: usize = 16;
+
+// Where a statement comes from, for error messages
+#[derive(Clone, Debug)]
+struct Location {
+    file: String,
+    line_nb: usize,
+}
+
+impl Location {
+    fn error(&self, reason: impl ToString) -> anyhow::Error {
+        anyhow!(AsmError::new(format!(
+            "{}:{} : {}",
+            self.file,
+            self.line_nb,
+            reason.to_string()
+        )))
+    }
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(String, Location),
+}
+
+#[derive(Default)]
+struct Assembler {
+    statements: Vec<(Location, Statement)>,
+    symbols: HashMap<String, Symbol>,
+    addr: u32,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.parse(source, "<source>", Path::new("."), 0)?;
+    assembler.encode()
+}
+
+// Same as `assemble`, with included files looked up next to the including one
+pub fn assemble_file(path: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.parse_file(
+        Path::new(path),
+        &Location {
+            file: path.to_string(),
+            line_nb: 0,
+        },
+        0,
+    )?;
+    assembler.encode()
+}
+
+fn is_register(word: &str) -> bool {
+    register(word).is_some()
+}
+
+fn register(word: &str) -> Option<u8> {
+    match word.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|reg| reg as u8),
+        _ => None,
+    }
+}
+
+// Bytes printed as `0b` or `0d` by the disassembler are still read as hexadecimal
+fn number(word: &str) -> Option<i64> {
+    let lower = word.to_ascii_lowercase();
+    let prefixed = if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else if let Some(dec) = lower.strip_prefix("0d") {
+        dec.parse().ok()
+    } else {
+        None
+    };
+    prefixed.or_else(|| i64::from_str_radix(lower.strip_prefix("0x").unwrap_or(&lower), 16).ok())
+}
+
+fn check_name(name: &str, location: &Location) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_chars = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid_chars || number(name).is_some() || is_register(name) {
+        return Err(location.error(format!(
+            "invalid name `{name}`, names start with a letter and can't be read as a number or register"
+        )));
+    }
+    Ok(())
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            addr: CHIP8_PC_START as u32,
+            ..Default::default()
+        }
+    }
+
+    fn parse_file(&mut self, path: &Path, location: &Location, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(location.error("too many nested includes"));
+        }
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| location.error(format!("cannot read {} : {err}", path.display())))?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.parse(&source, &path.display().to_string(), &dir, depth)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<()> {
+        check_name(name, location)?;
+        if self.symbols.contains_key(name) {
+            return Err(location.error(format!("`{name}` is already defined")));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn parse(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<()> {
+        for (i, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.to_string(),
+                line_nb: i + 1,
+            };
+            let mut line = line.split(';').next().unwrap_or_default().trim();
+
+            if let Some((label, rest)) = line.split_once(':') {
+                self.define(label.trim(), Symbol::Label(self.addr as u16), &location)?;
+                line = rest.trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((name, value)) = line.split_once('=') {
+                self.define(
+                    name.trim(),
+                    Symbol::Constant(value.trim().to_string(), location.clone()),
+                    &location,
+                )?;
+                continue;
+            }
+
+            let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let mnemonic = mnemonic.to_ascii_uppercase();
+            let operands: Vec<String> = rest
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|operand| !operand.is_empty())
+                .map(str::to_string)
+                .collect();
+            let (statement, len) = match mnemonic.as_str() {
+                "INCLUDE" => {
+                    let path: PathBuf = dir.join(rest.trim().trim_matches('"'));
+                    self.parse_file(&path, &location, depth + 1)?;
+                    continue;
+                }
+                "DB" => {
+                    let len = operands.len() as u32;
+                    (Statement::Bytes(operands), len)
+                }
+                "DW" => {
+                    let len = operands.len() as u32 * 2;
+                    (Statement::Words(operands), len)
+                }
+                "LSETI" => (Statement::Instruction { mnemonic, operands }, 4),
+                _ => (Statement::Instruction { mnemonic, operands }, 2),
+            };
+            self.addr += len;
+            if self.addr > 0x10000 {
+                return Err(location.error("program too large for the address space"));
+            }
+            self.statements.push((location, statement));
+        }
+        Ok(())
+    }
+
+    // Evaluates sums and differences of numbers, labels and constants
+    fn eval(&self, expr: &str, location: &Location, depth: usize) -> Result<i64> {
+        if depth > MAX_DEPTH {
+            return Err(location.error(format!("`{expr}` is defined recursively")));
+        }
+        let expr: String = expr.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expr.chars().chain(std::iter::once('+')) {
+            if c != '+' && c != '-' {
+                term.push(c);
+                continue;
+            }
+            if !term.is_empty() {
+                let value = match (number(&term), self.symbols.get(&term)) {
+                    (Some(value), _) => value,
+                    (None, Some(Symbol::Label(addr))) => *addr as i64,
+                    (None, Some(Symbol::Constant(value, def_location))) => {
+                        self.eval(value, def_location, depth + 1)?
+                    }
+                    (None, None) => return Err(location.error(format!("unknown value `{term}`"))),
+                };
+                total += sign * value;
+                term.clear();
+            } else if c == '+' {
+                return Err(location.error(format!("invalid expression `{expr}`")));
+            }
+            sign = if c == '-' { -sign } else { 1 };
+        }
+        Ok(total)
+    }
+
+    fn value(&self, operand: &str, bits: u32, location: &Location) -> Result<u16> {
+        let value = self.eval(operand, location, 0)?;
+        // Negative values are allowed, in two's complement
+        if value >= 1 << bits || value < -(1 << (bits - 1)) {
+            return Err(location.error(format!(
+                "`{operand}` = 0x{value:X} doesn't fit in {bits} bits"
+            )));
+        }
+        Ok((value as u16) & ((1u32 << bits) - 1) as u16)
+    }
+
+    fn encode_instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[String],
+        location: &Location,
+    ) -> Result<Vec<u16>> {
+        let reg = |i: usize| -> Result<u16> {
+            operands
+                .get(i)
+                .and_then(|operand| register(operand))
+                .map(|reg| reg as u16)
+                .ok_or_else(|| {
+                    location.error(format!(
+                        "{mnemonic} expects a register as operand {}",
+                        i + 1
+                    ))
+                })
+        };
+        let val = |i: usize, bits: u32| -> Result<u16> {
+            let operand = operands.get(i).ok_or_else(|| {
+                location.error(format!("{mnemonic} expects a value as operand {}", i + 1))
+            })?;
+            self.value(operand, bits, location)
+        };
+        let count = |n: usize| -> Result<()> {
+            if operands.len() != n {
+                return Err(location.error(format!(
+                    "{mnemonic} expects {n} operand(s), got {}",
+                    operands.len()
+                )));
+            }
+            Ok(())
+        };
+        let second_is_reg = operands.get(1).is_some_and(|operand| is_register(operand));
+
+        let opcode = match mnemonic {
+            "CLS" => count(0).map(|_| 0x00E0)?,
+            "RTS" => count(0).map(|_| 0x00EE)?,
+            "SCRD" => count(1).and(val(0, 4)).map(|n| 0x00C0 | n)?,
+            "SCRU" => count(1).and(val(0, 4)).map(|n| 0x00D0 | n)?,
+            "SCRR" => count(0).map(|_| 0x00FB)?,
+            "SCRL" => count(0).map(|_| 0x00FC)?,
+            "EXIT" => count(0).map(|_| 0x00FD)?,
+            "LORES" => count(0).map(|_| 0x00FE)?,
+            "HIRES" => count(0).map(|_| 0x00FF)?,
+            "JMP" => count(1).and(val(0, 12)).map(|nnn| 0x1000 | nnn)?,
+            "CALL" => count(1).and(val(0, 12)).map(|nnn| 0x2000 | nnn)?,
+            "SKIP.EQ" if second_is_reg => count(2).and(Ok(0x5000 | reg(0)? << 8 | reg(1)? << 4))?,
+            "SKIP.EQ" => count(2).and(Ok(0x3000 | reg(0)? << 8 | val(1, 8)?))?,
+            "SKIP.NE" | "SKIP.NEQ" if second_is_reg => {
+                count(2).and(Ok(0x9000 | reg(0)? << 8 | reg(1)? << 4))?
+            }
+            "SKIP.NE" => count(2).and(Ok(0x4000 | reg(0)? << 8 | val(1, 8)?))?,
+            "SAVE" => count(2).and(Ok(0x5002 | reg(0)? << 8 | reg(1)? << 4))?,
+            "RESTORE" => count(2).and(Ok(0x5003 | reg(0)? << 8 | reg(1)? << 4))?,
+            "SET" => count(2).and(Ok(0x6000 | reg(0)? << 8 | val(1, 8)?))?,
+            "ADD" if second_is_reg => count(2).and(Ok(0x8004 | reg(0)? << 8 | reg(1)? << 4))?,
+            "ADD" => count(2).and(Ok(0x7000 | reg(0)? << 8 | val(1, 8)?))?,
+            "MOV" | "OR" | "AND" | "XOR" | "SUB" | "SHR" | "RSUB" | "SHL" => {
+                let op = match mnemonic {
+                    "MOV" => 0x0,
+                    "OR" => 0x1,
+                    "AND" => 0x2,
+                    "XOR" => 0x3,
+                    "SUB" => 0x5,
+                    "SHR" => 0x6,
+                    "RSUB" => 0x7,
+                    _ => 0xE,
+                };
+                count(2).and(Ok(0x8000 | reg(0)? << 8 | reg(1)? << 4 | op))?
+            }
+            "SETI" => count(1).and(val(0, 12)).map(|nnn| 0xA000 | nnn)?,
+            "JR" => {
+                if reg(0)? != 0 {
+                    return Err(location.error("JR only jumps relatively to v0"));
+                }
+                count(2).and(val(1, 12)).map(|nnn| 0xB000 | nnn)?
+            }
+            "RAND" => count(2).and(Ok(0xC000 | reg(0)? << 8 | val(1, 8)?))?,
+            "DRAW" => count(3).and(Ok(0xD000 | reg(0)? << 8 | reg(1)? << 4 | val(2, 4)?))?,
+            "PRESS" => count(1).and(Ok(0xE09E | reg(0)? << 8))?,
+            "NPRESS" => count(1).and(Ok(0xE0A1 | reg(0)? << 8))?,
+            "LSETI" => return count(1).and(Ok(vec![0xF000, val(0, 16)?])),
+            "PLANE" => count(1).and(val(0, 4)).map(|n| 0xF001 | n << 8)?,
+            "AUDIO" => count(0).map(|_| 0xF002)?,
+            "GETD" | "WAITKEY" | "SETD" | "GETS" | "ADDI" | "LOADFNT" | "LOADBFNT" | "PITCH"
+            | "DCB" => {
+                let op = match mnemonic {
+                    "GETD" => 0x07,
+                    "WAITKEY" => 0x0A,
+                    "SETD" => 0x15,
+                    "GETS" => 0x18,
+                    "ADDI" => 0x1E,
+                    "LOADFNT" => 0x29,
+                    "LOADBFNT" => 0x30,
+                    "PITCH" => 0x3A,
+                    _ => 0x33,
+                };
+                count(1).and(Ok(0xF000 | reg(0)? << 8 | op))?
+            }
+            "STORE" | "LOAD" | "SAVEFLAGS" | "LOADFLAGS" => {
+                let op = match mnemonic {
+                    "STORE" => 0x55,
+                    "LOAD" => 0x65,
+                    "SAVEFLAGS" => 0x75,
+                    _ => 0x85,
+                };
+                count(1).and(val(0, 4)).map(|n| 0xF000 | n << 8 | op)?
+            }
+            _ => return Err(location.error(format!("unknown instruction `{mnemonic}`"))),
+        };
+        Ok(vec![opcode])
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut rom = Vec::new();
+        for (location, statement) in &self.statements {
+            match statement {
+                Statement::Instruction { mnemonic, operands } => self
+                    .encode_instruction(mnemonic, operands, location)?
+                    .iter()
+                    .for_each(|word| rom.extend(word.to_be_bytes())),
+                Statement::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.value(value, 8, location)? as u8);
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        rom.extend(self.value(value, 16, location)?.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disas::disassemble_rom, octo::compile, systems::Platform};
+
+    const PROGRAMS: [&str; 4] = [
+        include_str!("../tests/programs/font.8o"),
+        include_str!("../tests/programs/flags.8o"),
+        include_str!("../tests/programs/keypad.8o"),
+        include_str!("../tests/programs/quirks.8o"),
+    ];
+
+    fn assert_error(source: &str, reason: &str) {
+        let err = assemble(source).unwrap_err().to_string();
+        assert!(err.contains(reason), "`{err}` doesn't contain `{reason}`");
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        for platform in [Platform::Chip8, Platform::SChip, Platform::XoChip] {
+            for program in PROGRAMS {
+                let rom = compile(program, platform).unwrap();
+                let listing = disassemble_rom(&rom).unwrap().to_string();
+                assert_eq!(assemble(&listing).unwrap(), rom, "{listing}");
+            }
+        }
+    }
+
+    #[test]
+    fn labels_and_constants() {
+        let rom = assemble("x = 0d10\nstart: SET v0, x+1\n JMP start\nsprite: db 0b10000001 ff\nSETI sprite");
+        let rom = rom.unwrap();
+        assert_eq!(rom, [0x60, 0x0B, 0x12, 0x00, 0x81, 0xFF, 0xA2, 0x04]);
+    }
+
+    #[test]
+    fn unknown_label() {
+        assert_error("JMP nowhere", "<source>:1 : unknown value `nowhere`");
+    }
+
+    #[test]
+    fn recursive_constants() {
+        assert_error("foo = bar\nbar = foo\nSET v0, foo", "is defined recursively");
+    }
+
+    #[test]
+    fn nested_includes() {
+        let path = std::env::temp_dir().join(format!("rusty-chip8-asm-{}.asm", std::process::id()));
+        std::fs::write(&path, format!("include \"{}\"", path.display())).unwrap();
+        let err = assemble_file(&path.to_string_lossy()).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.contains("too many nested includes"), "{err}");
+    }
+
+    #[test]
+    fn out_of_range_operands() {
+        assert_error("SET v0, 100", "`100` = 0x100 doesn't fit in 8 bits");
+        assert_error("DRAW v0, v1, 10", "doesn't fit in 4 bits");
+        assert_error("JMP 1000", "doesn't fit in 12 bits");
+        assert_error("db -81", "doesn't fit in 8 bits");
+        assert_error("SET v0", "SET expects a value as operand 2");
+        assert_error("CLS v0", "CLS expects 0 operand(s), got 1");
+    }
+}
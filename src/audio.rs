@@ -0,0 +1,94 @@
+use {
+    rodio::{Sink, Source, source::SineWave},
+    rusty_chip8::systems::BuzzerState,
+    std::time::Duration,
+};
+
+const SAMPLE_RATE: u32 = 44100;
+
+// Plays an XO-CHIP 128 bit audio pattern in a loop, at a playback rate set by the pitch register
+pub struct PatternSource {
+    pattern: [u8; 0x10],
+    // bits of the pattern played per output sample
+    step: f64,
+    pos: f64,
+}
+
+impl PatternSource {
+    pub fn new(pattern: [u8; 0x10], pitch: u8) -> Self {
+        let rate = 4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0);
+        Self {
+            pattern,
+            step: rate / SAMPLE_RATE as f64,
+            pos: 0.0,
+        }
+    }
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit = self.pos as usize % 128;
+        self.pos = (self.pos + self.step) % 128.0;
+        Some(if self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 0.2 } else { -0.2 })
+    }
+}
+
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Follows the buzzer state of the emulated system on an audio sink
+pub struct Beeper {
+    sink: Sink,
+    playing: BuzzerState,
+}
+
+impl Beeper {
+    pub fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            playing: BuzzerState::Off,
+        }
+    }
+
+    // From 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.sink.set_volume(volume.clamp(0.0, 1.0));
+    }
+
+    pub fn update(&mut self, state: BuzzerState) {
+        if state == self.playing {
+            return;
+        }
+        self.sink.clear();
+        match state {
+            BuzzerState::Off => (),
+            BuzzerState::Tone => {
+                // TODO: custom square wave ?
+                self.sink.append(SineWave::new(440.0).amplify(0.20));
+                self.sink.play();
+            }
+            BuzzerState::Pattern { pattern, pitch } => {
+                self.sink.append(PatternSource::new(pattern, pitch));
+                self.sink.play();
+            }
+        }
+        self.playing = state;
+    }
+}
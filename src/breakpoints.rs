@@ -0,0 +1,440 @@
+use {
+    anyhow::{Result, anyhow},
+    std::fmt,
+};
+
+use crate::{
+    errors::BreakpointError,
+    mem::{AccessKind, Memory16Bit},
+    systems::{Chip8, Register},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+// Accesses a watchpoint triggers on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    // Breaks before executing the instruction at this address
+    Pc(u16),
+    // Breaks after an instruction read or wrote any byte of this (inclusive) range
+    Watch {
+        start: u16,
+        end: u16,
+        kind: WatchKind,
+    },
+    // Breaks once the condition becomes true
+    Register {
+        reg: Register,
+        cmp: Comparison,
+        value: u16,
+    },
+    // Breaks before executing an instruction matching `value` on the bits of `mask` (e.g. DXYN)
+    Opcode {
+        mask: u16,
+        value: u16,
+    },
+}
+
+fn parse_number(word: &str) -> Result<u16> {
+    u16::from_str_radix(word.trim_start_matches("0x"), 16).map_err(|_| {
+        anyhow!(BreakpointError::new(format!(
+            "`{word}` isn't a hexadecimal number"
+        )))
+    })
+}
+
+impl Comparison {
+    fn holds(&self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl Breakpoint {
+    // Parses the following forms, numbers being hexadecimal :
+    // - `200` or `pc 200` : program counter
+    // - `read 300`, `write 300 30f` or `access 300 30f` : memory watchpoint, on a byte or a range
+    // - `vA == 10`, `i >= 300`, `dt != 0`... : register condition, on v0-vF, i, pc, sp, dt and st
+    // - `op DXYN`, `op FX0A`... : opcode class, hex digits (in either case) matching themselves and
+    //   any other letter matching any nibble. A-F can't be wildcards : `op DXYA` matches `DXY` with
+    //   any X and Y, but only sprites 10 lines high.
+    pub fn parse(text: &str) -> Result<Self> {
+        let err = |reason: &str| anyhow!(BreakpointError::new(format!("{reason} in `{text}`")));
+        if let Some((lhs, cmp, rhs)) = COMPARISONS.iter().find_map(|(symbol, cmp)| {
+            text.split_once(symbol)
+                .map(|(lhs, rhs)| (lhs.trim(), *cmp, rhs.trim()))
+        }) {
+            let reg = Register::from_name(lhs).ok_or_else(|| err("unknown register"))?;
+            return Ok(Self::Register {
+                reg,
+                cmp,
+                value: parse_number(rhs)?,
+            });
+        }
+
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [addr] | ["pc", addr] => Ok(Self::Pc(parse_number(addr)?)),
+            [kind @ ("read" | "write" | "access"), start, end @ ..] if end.len() <= 1 => {
+                let start = parse_number(start)?;
+                let end = match end {
+                    [end] => parse_number(end)?,
+                    _ => start,
+                };
+                if end < start {
+                    return Err(err("empty range"));
+                }
+                let kind = match *kind {
+                    "read" => WatchKind::Read,
+                    "write" => WatchKind::Write,
+                    _ => WatchKind::Access,
+                };
+                Ok(Self::Watch { start, end, kind })
+            }
+            ["op", pattern] if pattern.len() == 4 => {
+                let (mut mask, mut value) = (0, 0);
+                for c in pattern.chars() {
+                    mask <<= 4;
+                    value <<= 4;
+                    if let Some(nibble) = c.to_digit(16) {
+                        mask |= 0xF;
+                        value |= nibble as u16;
+                    } else if !c.is_ascii_alphabetic() {
+                        return Err(err("invalid opcode pattern"));
+                    }
+                }
+                Ok(Self::Opcode { mask, value })
+            }
+            _ => Err(err("unknown breakpoint")),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pc(addr) => write!(f, "pc {addr:03x}"),
+            Self::Watch { start, end, kind } => {
+                let kind = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Access => "access",
+                };
+                if start == end {
+                    write!(f, "{kind} {start:03x}")
+                } else {
+                    write!(f, "{kind} {start:03x} {end:03x}")
+                }
+            }
+            Self::Register { reg, cmp, value } => {
+                let cmp = COMPARISONS
+                    .iter()
+                    .find(|(_, comparison)| comparison == cmp)
+                    .map(|(symbol, _)| *symbol)
+                    .unwrap_or_default();
+                write!(f, "{reg} {cmp} {value:x}")
+            }
+            Self::Opcode { mask, value } => {
+                let pattern: String = (0..4)
+                    .rev()
+                    .map(|nibble| match mask >> (nibble * 4) & 0xF {
+                        0 => 'X',
+                        _ => char::from_digit((value >> (nibble * 4) & 0xF) as u32, 16)
+                            .unwrap_or_default()
+                            .to_ascii_uppercase(),
+                    })
+                    .collect();
+                write!(f, "op {pattern}")
+            }
+        }
+    }
+}
+
+// Breakpoints set on a machine, checked after each instruction
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    // Whether each register condition held after the previous instruction, as they only break
+    // when becoming true
+    conditions_held: Vec<bool>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+            self.conditions_held.push(false);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Breakpoint> {
+        if index >= self.breakpoints.len() {
+            return None;
+        }
+        self.conditions_held.remove(index);
+        Some(self.breakpoints.remove(index))
+    }
+
+    pub fn get(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    // Returns the first breakpoint hit by the instruction just executed, if any
+    pub fn check(&mut self, chip8: &Chip8) -> Option<Breakpoint> {
+        let mut hit = None;
+        let pc = chip8.get_state().pc;
+        let opcode = chip8
+            .get_mem()
+            .get(pc, 2)
+            .map(|op| u16::from_be_bytes([op[0], op[1]]))
+            .ok();
+        for (breakpoint, held) in self.breakpoints.iter().zip(self.conditions_held.iter_mut()) {
+            let is_hit = match breakpoint {
+                Breakpoint::Pc(addr) => pc == *addr,
+                Breakpoint::Watch { start, end, kind } => {
+                    chip8.get_mem().get_accesses().iter().any(|access| {
+                        let kind_matches = match kind {
+                            WatchKind::Read => access.kind == AccessKind::Read,
+                            WatchKind::Write => access.kind == AccessKind::Write,
+                            WatchKind::Access => true,
+                        };
+                        let access_end = access.addr as u32 + access.len as u32;
+                        kind_matches && access.addr <= *end && access_end > *start as u32
+                    })
+                }
+                Breakpoint::Register { reg, cmp, value } => {
+                    let holds = cmp.holds(chip8.get_register(*reg), *value);
+                    let became_true = holds && !*held;
+                    *held = holds;
+                    became_true
+                }
+                Breakpoint::Opcode { mask, value } => opcode.is_some_and(|op| op & mask == *value),
+            };
+            if is_hit && hit.is_none() {
+                hit = Some(*breakpoint);
+            }
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Platform, System};
+
+    // Program drawing `I` at 300 (read), storing v0 at 310 (write), then looping
+    const PROGRAM: [u8; 12] = [
+        0xA3, 0x00, 0xD0, 0x01, 0xA3, 0x10, 0xF0, 0x55, 0x70, 0x01, 0x12, 0x00,
+    ];
+
+    fn start() -> Chip8 {
+        let mut quirks = Platform::Chip8.default_quirks();
+        quirks.display_wait = false;
+        let mut chip8 = Chip8::init(Platform::Chip8, quirks, Some(0));
+        chip8.load_program(&PROGRAM).unwrap();
+        chip8
+    }
+
+    // Runs instructions until one hits a breakpoint, returning it and the PC then
+    fn run_until_hit(chip8: &mut Chip8, breakpoints: &mut Breakpoints) -> (Breakpoint, u16) {
+        for _ in 0..100 {
+            chip8.get_mem_mut().clear_accesses();
+            chip8.exec_instruction().unwrap();
+            if let Some(hit) = breakpoints.check(chip8) {
+                return (hit, chip8.get_register(Register::Pc));
+            }
+        }
+        panic!("no breakpoint hit");
+    }
+
+    #[test]
+    fn parse() {
+        let parse = |text| Breakpoint::parse(text).unwrap();
+        assert_eq!(parse("200"), Breakpoint::Pc(0x200));
+        assert_eq!(parse("pc 0x2a0"), Breakpoint::Pc(0x2A0));
+        let watch = |start, end, kind| Breakpoint::Watch { start, end, kind };
+        assert_eq!(parse("read 300"), watch(0x300, 0x300, WatchKind::Read));
+        assert_eq!(
+            parse("write 300 30f"),
+            watch(0x300, 0x30F, WatchKind::Write)
+        );
+        assert_eq!(
+            parse("access 300 30F"),
+            watch(0x300, 0x30F, WatchKind::Access)
+        );
+        let cmp = |reg, cmp, value| Breakpoint::Register { reg, cmp, value };
+        assert_eq!(
+            parse("vA == 10"),
+            cmp(Register::V(0xA), Comparison::Eq, 0x10)
+        );
+        assert_eq!(parse("i>=300"), cmp(Register::I, Comparison::Ge, 0x300));
+        assert_eq!(parse("dt != 0"), cmp(Register::Delay, Comparison::Ne, 0));
+        assert_eq!(parse("sp < ea0"), cmp(Register::Sp, Comparison::Lt, 0xEA0));
+
+        // Hex digits are literal, whatever their case, any other letter being a wildcard
+        let op = |mask, value| Breakpoint::Opcode { mask, value };
+        assert_eq!(parse("op DXYN"), op(0xF000, 0xD000));
+        assert_eq!(parse("op dxyn"), op(0xF000, 0xD000));
+        assert_eq!(parse("op FX0A"), op(0xF0FF, 0xF00A));
+        assert_eq!(parse("op 8XYE"), op(0xF00F, 0x800E));
+        assert_eq!(parse("op DXYA"), op(0xF00F, 0xD00A));
+        assert_eq!(parse("op 00E0"), op(0xFFFF, 0x00E0));
+
+        for text in [
+            "",
+            "pc",
+            "zzz",
+            "write 30f 300",
+            "read",
+            "vG == 1",
+            "v0 == zz",
+            "op DXY",
+            "op DX_N",
+            "op DXYNN",
+        ] {
+            assert!(Breakpoint::parse(text).is_err(), "`{text}` parses");
+        }
+    }
+
+    #[test]
+    fn display() {
+        for text in [
+            "pc 200",
+            "read 300",
+            "write 300 30f",
+            "access 300 30f",
+            "vA == 10",
+            "i >= 300",
+            "op FX0A",
+        ] {
+            assert_eq!(
+                Breakpoint::parse(text).unwrap().to_string().to_lowercase(),
+                text.to_lowercase()
+            );
+        }
+        // Wildcards are shown as X
+        assert_eq!(Breakpoint::parse("op DXYN").unwrap().to_string(), "op DXXX");
+        assert_eq!(Breakpoint::parse("op 8nne").unwrap().to_string(), "op 8XXE");
+    }
+
+    #[test]
+    fn pc_hits() {
+        let mut chip8 = start();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add(Breakpoint::Pc(0x208));
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints),
+            (Breakpoint::Pc(0x208), 0x208)
+        );
+        // Once per loop
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints),
+            (Breakpoint::Pc(0x208), 0x208)
+        );
+        assert_eq!(chip8.get_register(Register::V(0)), 1);
+    }
+
+    #[test]
+    fn watch_hits() {
+        let mut chip8 = start();
+        let mut breakpoints = Breakpoints::new();
+        let write = Breakpoint::parse("write 2ff 300").unwrap();
+        let read = Breakpoint::parse("read 300").unwrap();
+        let access = Breakpoint::parse("access 30f 310").unwrap();
+        // The sprite is read, but not written
+        breakpoints.add(write);
+        breakpoints.add(read);
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints), (read, 0x204));
+        breakpoints.add(access);
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints), (access, 0x208));
+        // Nothing else accessed during the loop
+        breakpoints.remove(2);
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints), (read, 0x204));
+    }
+
+    #[test]
+    fn register_hits() {
+        let mut chip8 = start();
+        let mut breakpoints = Breakpoints::new();
+        let condition = Breakpoint::parse("v0 >= 2").unwrap();
+        breakpoints.add(condition);
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints),
+            (condition, 0x20A)
+        );
+        assert_eq!(chip8.get_register(Register::V(0)), 2);
+        // Only when becoming true, not while it holds
+        breakpoints.add(Breakpoint::Pc(0x208));
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints).0,
+            Breakpoint::Pc(0x208)
+        );
+        chip8.set_register(Register::V(0), 0).unwrap();
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints).0,
+            Breakpoint::Pc(0x208)
+        );
+        assert_eq!(
+            run_until_hit(&mut chip8, &mut breakpoints),
+            (condition, 0x20A)
+        );
+    }
+
+    #[test]
+    fn opcode_hits() {
+        let mut chip8 = start();
+        let mut breakpoints = Breakpoints::new();
+        let store = Breakpoint::parse("op FX55").unwrap();
+        let draw = Breakpoint::parse("op DXYN").unwrap();
+        breakpoints.add(store);
+        // Before executing the instruction
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints), (store, 0x206));
+        breakpoints.add(draw);
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints), (draw, 0x202));
+        // Only sprites 2 lines high
+        breakpoints.remove(1);
+        breakpoints.remove(0);
+        breakpoints.add(Breakpoint::parse("op DXY2").unwrap());
+        breakpoints.add(Breakpoint::parse("op 12NN").unwrap());
+        assert_eq!(run_until_hit(&mut chip8, &mut breakpoints).1, 0x20A);
+    }
+}
@@ -0,0 +1,236 @@
+use {
+    rusty_chip8::{
+        debugger::FaultPolicies,
+        systems::{Platform, Quirks},
+        trace::TraceFormat,
+    },
+    std::{ops::RangeInclusive, process::exit},
+};
+
+const DEFAULT_REWIND_SECS: usize = 180;
+const DEFAULT_TEST_FRAMES: u32 = 300;
+
+pub struct Args {
+    // Optional when running in a window, programs being openable from its File menu
+    pub path: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub rewind_secs: usize,
+    pub seed: Option<u64>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub keymap: Option<String>,
+    // Prints the program listing instead of running it
+    pub disassemble: bool,
+    // Assembles the program source to the given ROM file instead of running it
+    pub assemble: Option<String>,
+    // Port for gdb to connect to, the program starting paused
+    pub gdb_port: Option<u16>,
+    // Runs the program from a command prompt instead of a window
+    pub debug: bool,
+    // Writes the executed instructions to this file, optionally only those within the given
+    // addresses and frames
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_addrs: Option<RangeInclusive<u16>>,
+    pub trace_frames: Option<RangeInclusive<u64>>,
+    // Binary trace to print as text, instead of running anything
+    pub print_trace: Option<String>,
+    pub fault_policies: FaultPolicies,
+    // Runs the program headless for `test_frames` frames, then compares its display with the
+    // `expect` image and/or writes it to `dump`
+    pub test_rom: bool,
+    pub test_frames: u32,
+    pub expect: Option<String>,
+    pub dump: Option<String>,
+    // Bytes written to memory once the program is loaded, e.g. to pick a test from a menu
+    pub pokes: Vec<(u16, u8)>,
+}
+
+fn usage() -> ! {
+    println!(
+        "Usage : emu [--platform chip8|schip|xochip] [--quirks vip|chip48|schip|xochip] \
+         [--ipf instructions per frame] [--rewind seconds] [--seed N] \
+         [--record movie | --play movie] [--keymap file] [--disassemble | --assemble rom] [--gdb port | --debug] \
+         [--trace file [--trace-format text|binary] [--trace-pc start-end] [--trace-frames start-end]] [--print-trace file] \
+         [--on-fault [kind=]halt|ignore|break|reset,...] \
+         [--test-rom [--frames N] [--expect image.pbm] [--dump image.pbm] [--poke addr=value]...] [CHIP-8 program]"
+    );
+    exit(1);
+}
+
+// `start-end`, or a single value
+fn parse_range<T: PartialOrd>(
+    text: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<RangeInclusive<T>> {
+    let (start, end) = text.split_once('-').unwrap_or((text, text));
+    let (start, end) = (parse(start)?, parse(end)?);
+    (start <= end).then_some(start..=end)
+}
+
+impl Args {
+    // rather use GUI techniques
+    pub fn parse() -> Self {
+        let mut path = None;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut cycles_per_frame = None;
+        let mut rewind_secs = DEFAULT_REWIND_SECS;
+        let mut seed = None;
+        let mut record = None;
+        let mut play = None;
+        let mut keymap = None;
+        let mut disassemble = false;
+        let mut assemble = None;
+        let mut gdb_port = None;
+        let mut debug = false;
+        let mut trace = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut trace_addrs = None;
+        let mut trace_frames = None;
+        let mut print_trace = None;
+        let mut fault_policies = FaultPolicies::default();
+        let mut test_rom = false;
+        let mut test_frames = DEFAULT_TEST_FRAMES;
+        let mut expect = None;
+        let mut dump = None;
+        let mut pokes = Vec::new();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--platform" => {
+                    platform = match args.next().as_deref().and_then(Platform::from_name) {
+                        Some(platform) => Some(platform),
+                        None => usage(),
+                    }
+                }
+                "--quirks" => {
+                    quirks = match args.next().as_deref().and_then(Quirks::from_name) {
+                        Some(quirks) => Some(quirks),
+                        None => usage(),
+                    }
+                }
+                "--ipf" => {
+                    cycles_per_frame = match args.next().and_then(|ipf| ipf.parse().ok()) {
+                        Some(ipf) => Some(ipf),
+                        None => usage(),
+                    }
+                }
+                "--rewind" => {
+                    rewind_secs = match args.next().and_then(|secs| secs.parse().ok()) {
+                        Some(secs) => secs,
+                        None => usage(),
+                    }
+                }
+                "--seed" => {
+                    seed = match args.next().and_then(|seed| seed.parse().ok()) {
+                        Some(seed) => Some(seed),
+                        None => usage(),
+                    }
+                }
+                "--record" => record = Some(args.next().unwrap_or_else(|| usage())),
+                "--play" => play = Some(args.next().unwrap_or_else(|| usage())),
+                "--keymap" => keymap = Some(args.next().unwrap_or_else(|| usage())),
+                "--disassemble" => disassemble = true,
+                "--assemble" => assemble = Some(args.next().unwrap_or_else(|| usage())),
+                "--gdb" => {
+                    gdb_port = match args.next().and_then(|port| port.parse().ok()) {
+                        Some(port) => Some(port),
+                        None => usage(),
+                    }
+                }
+                "--debug" => debug = true,
+                "--trace" => trace = Some(args.next().unwrap_or_else(|| usage())),
+                "--print-trace" => print_trace = Some(args.next().unwrap_or_else(|| usage())),
+                "--trace-format" => {
+                    trace_format = match args.next().as_deref().and_then(TraceFormat::from_name) {
+                        Some(format) => format,
+                        None => usage(),
+                    }
+                }
+                "--trace-pc" => {
+                    trace_addrs = match args.next().and_then(|addrs| {
+                        parse_range(&addrs, |addr| u16::from_str_radix(addr, 16).ok())
+                    }) {
+                        Some(addrs) => Some(addrs),
+                        None => usage(),
+                    }
+                }
+                "--trace-frames" => {
+                    trace_frames = match args
+                        .next()
+                        .and_then(|frames| parse_range(&frames, |frame| frame.parse().ok()))
+                    {
+                        Some(frames) => Some(frames),
+                        None => usage(),
+                    }
+                }
+                "--on-fault" => {
+                    fault_policies = match args.next().as_deref().and_then(FaultPolicies::from_name) {
+                        Some(policies) => policies,
+                        None => usage(),
+                    }
+                }
+                "--test-rom" => test_rom = true,
+                "--frames" => {
+                    test_frames = match args.next().and_then(|frames| frames.parse().ok()) {
+                        Some(frames) => frames,
+                        None => usage(),
+                    }
+                }
+                "--expect" => expect = Some(args.next().unwrap_or_else(|| usage())),
+                "--dump" => dump = Some(args.next().unwrap_or_else(|| usage())),
+                "--poke" => {
+                    let poke = args.next().and_then(|poke| {
+                        let (addr, value) = poke.split_once('=')?;
+                        Some((
+                            u16::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?,
+                            u8::from_str_radix(value.trim_start_matches("0x"), 16).ok()?,
+                        ))
+                    });
+                    match poke {
+                        Some(poke) => pokes.push(poke),
+                        None => usage(),
+                    }
+                }
+                _ => path = Some(arg),
+            }
+        }
+        if path.is_none()
+            && (disassemble || assemble.is_some() || debug || test_rom || record.is_some() || play.is_some())
+            || record.is_some() && play.is_some()
+            || debug && (record.is_some() || play.is_some() || gdb_port.is_some())
+        {
+            usage();
+        }
+
+        Self {
+            path,
+            platform,
+            quirks,
+            cycles_per_frame,
+            rewind_secs,
+            seed,
+            record,
+            play,
+            keymap,
+            disassemble,
+            assemble,
+            gdb_port,
+            debug,
+            trace,
+            trace_format,
+            trace_addrs,
+            trace_frames,
+            print_trace,
+            fault_policies,
+            test_rom,
+            test_frames,
+            expect,
+            dump,
+            pokes,
+        }
+    }
+}
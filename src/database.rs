@@ -0,0 +1,363 @@
+use {
+    anyhow::{Result, anyhow},
+    serde::Deserialize,
+    std::collections::HashMap,
+};
+
+use crate::{
+    errors::DatabaseError,
+    systems::{MemoryIncrement, Palette, Platform, Quirks},
+};
+
+// Programs known to the CHIP-8 community database (https://github.com/chip-8/chip-8-database),
+// identified by the SHA-1 of their ROM, along with the platform and settings they need. Its
+// `sha1-hashes.json`, `programs.json` and `platforms.json` files are read as is, fields this
+// emulator doesn't use being ignored. A release of the database is bundled in `chip-8-database/`
+// (MIT licensed, see its LICENSE.md).
+const BUNDLED_SHA1_HASHES: &str = include_str!("../chip-8-database/sha1-hashes.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../chip-8-database/programs.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../chip-8-database/platforms.json");
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbQuirks {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl DbQuirks {
+    // Quirks given by `self` override those of `base`
+    fn apply(&self, base: Quirks) -> Quirks {
+        let memory_increment = match (self.memory_leave_i_unchanged, self.memory_increment_by_x) {
+            (Some(true), _) => MemoryIncrement::Unchanged,
+            (_, Some(true)) => MemoryIncrement::X,
+            (None, None) => base.memory_increment,
+            _ => MemoryIncrement::XPlusOne,
+        };
+        Quirks {
+            shift: self.shift.unwrap_or(base.shift),
+            vf_reset: self.logic.unwrap_or(base.vf_reset),
+            memory_increment,
+            jump_vx: self.jump.unwrap_or(base.jump_vx),
+            display_wait: self.vblank.unwrap_or(base.display_wait),
+            clipping: self.wrap.map_or(base.clipping, |wrap| !wrap),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbPlatform {
+    id: String,
+    name: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: DbQuirks,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct DbColors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbRom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, DbQuirks>,
+    tickrate: Option<u32>,
+    colors: Option<DbColors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DbProgram {
+    title: String,
+    description: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, DbRom>,
+}
+
+// What the database knows of a program, settings left unset when it doesn't give them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub description: Option<String>,
+    // Name of the platform the program was made for, even if it can't be emulated
+    pub platform_name: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub palette: Option<Palette>,
+    // Keypad key of each action, e.g. `up` or `a`, sorted by action
+    pub keys: Vec<(String, u8)>,
+}
+
+// Platform of the emulator running programs made for the given database platform
+fn platform_from_id(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" | "chip48" => Some(Platform::Chip8),
+        "superchip1" | "superchip" => Some(Platform::SChip),
+        "xochip" => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+// `#rrggbb`, or the `#rgb` shorthand
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#')?;
+    let channel = |i: usize, len: usize| {
+        let value = u8::from_str_radix(hex.get(i * len..(i + 1) * len)?, 16).ok()?;
+        Some(if len == 1 { value * 0x11 } else { value })
+    };
+    let len = match hex.len() {
+        3 => 1,
+        6 => 2,
+        _ => return None,
+    };
+    Some([channel(0, len)?, channel(1, len)?, channel(2, len)?])
+}
+
+// Programs are drawn with the colours of the 2 planes, falling back to ours for the missing ones
+fn parse_palette(colors: &DbColors) -> Option<Palette> {
+    let default = Palette::default();
+    let color = |i: usize, default: [u8; 3]| colors.pixels.get(i).and_then(|color| parse_color(color)).unwrap_or(default);
+    (colors.pixels.len() >= 2).then(|| Palette {
+        background: color(0, default.background),
+        foreground: color(1, default.foreground),
+        plane2: color(2, default.plane2),
+        both_planes: color(3, default.both_planes),
+    })
+}
+
+pub struct Database {
+    hashes: HashMap<String, usize>,
+    programs: Vec<DbProgram>,
+    platforms: Vec<DbPlatform>,
+}
+
+impl Database {
+    // Takes the contents of `sha1-hashes.json`, `programs.json` and `platforms.json`
+    pub fn parse(sha1_hashes: &str, programs: &str, platforms: &str) -> Result<Self> {
+        let err = |file: &str, err: serde_json::Error| anyhow!(DatabaseError::new(format!("{file} : {err}")));
+        let hashes: HashMap<String, usize> =
+            serde_json::from_str(sha1_hashes).map_err(|e| err("sha1-hashes.json", e))?;
+        let programs: Vec<DbProgram> = serde_json::from_str(programs).map_err(|e| err("programs.json", e))?;
+        let platforms = serde_json::from_str(platforms).map_err(|e| err("platforms.json", e))?;
+        if let Some((sha1, _)) = hashes.iter().find(|(_, index)| **index >= programs.len()) {
+            return Err(anyhow!(DatabaseError::new(format!(
+                "sha1-hashes.json : {sha1} refers to a missing program"
+            ))));
+        }
+        Ok(Self {
+            hashes,
+            programs,
+            platforms,
+        })
+    }
+
+    // Copy built into the emulator
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_SHA1_HASHES, BUNDLED_PROGRAMS, BUNDLED_PLATFORMS)
+            .expect("The bundled database is valid")
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    // `program_sha1` is the hexadecimal SHA-1 of the ROM, in lowercase
+    pub fn lookup(&self, program_sha1: &str) -> Option<ProgramInfo> {
+        let program = self.programs.get(*self.hashes.get(program_sha1)?)?;
+        let rom = program.roms.get(program_sha1).cloned().unwrap_or_default();
+        // The first platform listed is the one the program was made for, any other one it runs
+        // on being preferred when it can't be emulated
+        let platform_id = rom
+            .platforms
+            .iter()
+            .find(|id| platform_from_id(id).is_some())
+            .or(rom.platforms.first());
+        let db_platform = platform_id.and_then(|id| self.platforms.iter().find(|platform| platform.id == *id));
+        let platform = platform_id.and_then(|id| platform_from_id(id));
+        let quirks = platform.zip(db_platform).map(|(platform, db_platform)| {
+            let quirks = db_platform.quirks.apply(platform.default_quirks());
+            match rom.quirky_platforms.get(&db_platform.id) {
+                Some(rom_quirks) => rom_quirks.apply(quirks),
+                None => quirks,
+            }
+        });
+        let mut keys: Vec<(String, u8)> = rom.keys.into_iter().filter(|(_, key)| *key < 0x10).collect();
+        keys.sort();
+        Some(ProgramInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            release: program.release.clone(),
+            description: program.description.clone(),
+            platform_name: db_platform
+                .map(|platform| platform.name.clone())
+                .or_else(|| platform_id.cloned()),
+            platform,
+            quirks,
+            cycles_per_frame: rom
+                .tickrate
+                .or(db_platform.and_then(|platform| platform.default_tickrate))
+                .filter(|_| platform.is_some()),
+            palette: rom.colors.as_ref().and_then(parse_palette),
+            keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_HASHES: &str = r#"{"aaaa": 0, "bbbb": 1, "cccc": 1, "dddd": 2}"#;
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Pong",
+            "authors": ["Paul Vervalin"],
+            "roms": {
+                "aaaa": {
+                    "platforms": ["originalChip8"],
+                    "colors": {"pixels": ["#000", "#ff8000"]},
+                    "keys": {"up": 1, "down": 4, "invalid": 16}
+                }
+            }
+        },
+        {
+            "title": "Quirky",
+            "release": "1991",
+            "roms": {
+                "bbbb": {
+                    "platforms": ["megachip8", "superchip"],
+                    "quirkyPlatforms": {"superchip": {"wrap": true, "memoryLeaveIUnchanged": false}},
+                    "tickrate": 50
+                },
+                "cccc": {"platforms": ["chip48"]}
+            }
+        },
+        {"title": "Mega", "roms": {"dddd": {"platforms": ["megachip8"]}}}
+    ]"##;
+    const PLATFORMS: &str = r#"[
+        {
+            "id": "originalChip8",
+            "name": "Cosmac VIP CHIP-8",
+            "defaultTickrate": 15,
+            "quirks": {"shift": false, "memoryIncrementByX": false, "memoryLeaveIUnchanged": false,
+                "wrap": false, "jump": false, "vblank": true, "logic": true}
+        },
+        {
+            "id": "chip48",
+            "name": "CHIP-48",
+            "defaultTickrate": 30,
+            "quirks": {"shift": true, "memoryIncrementByX": true, "memoryLeaveIUnchanged": false,
+                "wrap": false, "jump": true, "vblank": false, "logic": false}
+        },
+        {
+            "id": "superchip",
+            "name": "Superchip",
+            "defaultTickrate": 30,
+            "quirks": {"shift": true, "memoryIncrementByX": false, "memoryLeaveIUnchanged": true,
+                "wrap": false, "jump": true, "vblank": false, "logic": false}
+        }
+    ]"#;
+
+    fn database() -> Database {
+        Database::parse(SHA1_HASHES, PROGRAMS, PLATFORMS).unwrap()
+    }
+
+    #[test]
+    fn platform_quirks() {
+        let database = database();
+        assert_eq!(database.len(), 4);
+        let pong = database.lookup("aaaa").unwrap();
+        assert_eq!(pong.title, "Pong");
+        assert_eq!(pong.authors, ["Paul Vervalin"]);
+        assert_eq!(pong.platform_name.as_deref(), Some("Cosmac VIP CHIP-8"));
+        assert_eq!(pong.platform, Some(Platform::Chip8));
+        assert_eq!(pong.quirks, Some(Quirks::VIP));
+        assert_eq!(pong.cycles_per_frame, Some(15));
+        assert_eq!(pong.keys, [("down".to_string(), 4), ("up".to_string(), 1)]);
+
+        let chip48 = database.lookup("cccc").unwrap();
+        assert_eq!(chip48.platform, Some(Platform::Chip8));
+        assert_eq!(chip48.quirks, Some(Quirks::CHIP48));
+        assert_eq!(chip48.cycles_per_frame, Some(30));
+        assert_eq!(database.lookup("eeee"), None);
+    }
+
+    #[test]
+    fn quirky_platforms() {
+        // The platform that can be emulated is picked, with the quirks of this ROM
+        let quirky = database().lookup("bbbb").unwrap();
+        assert_eq!(quirky.platform_name.as_deref(), Some("Superchip"));
+        assert_eq!(quirky.platform, Some(Platform::SChip));
+        assert_eq!(
+            quirky.quirks,
+            Some(Quirks {
+                memory_increment: MemoryIncrement::XPlusOne,
+                clipping: false,
+                ..Quirks::SCHIP
+            })
+        );
+        assert_eq!(quirky.cycles_per_frame, Some(50));
+
+        let mega = database().lookup("dddd").unwrap();
+        assert_eq!(mega.platform_name.as_deref(), Some("megachip8"));
+        assert_eq!((mega.platform, mega.quirks, mega.cycles_per_frame), (None, None, None));
+    }
+
+    #[test]
+    fn colors() {
+        let palette = database().lookup("aaaa").unwrap().palette.unwrap();
+        assert_eq!(palette.background, [0, 0, 0]);
+        assert_eq!(palette.foreground, [0xFF, 0x80, 0]);
+        assert_eq!(palette.plane2, Palette::default().plane2);
+        assert_eq!(parse_color("#f80"), Some([0xFF, 0x88, 0]));
+        assert_eq!(parse_color("#ff8"), Some([0xFF, 0xFF, 0x88]));
+        assert_eq!(parse_color("f80"), None);
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#g80"), None);
+    }
+
+    #[test]
+    fn bundled_database() {
+        let database = Database::bundled();
+        assert!(database.len() > 500);
+        // Timendus' IBM logo test, made for the COSMAC VIP
+        let ibm_logo = database.lookup("d3554b9789728294d881823126ba6eb8103bd42c").unwrap();
+        assert_eq!(ibm_logo.title, "CHIP-8 test suite: IBM logo");
+        assert_eq!(ibm_logo.platform, Some(Platform::Chip8));
+        assert_eq!(ibm_logo.quirks, Some(Quirks::VIP));
+    }
+
+    #[test]
+    fn invalid_databases() {
+        let err = Database::parse(r#"{"aaaa": 3}"#, PROGRAMS, PLATFORMS).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "DatabaseError : sha1-hashes.json : aaaa refers to a missing program"
+        );
+        let err = Database::parse(SHA1_HASHES, "{}", PLATFORMS).err().unwrap();
+        assert!(err.to_string().starts_with("DatabaseError : programs.json : "), "{err}");
+    }
+}
@@ -1,7 +1,7 @@
 use core::fmt;
 use std::fmt::{Display, LowerHex};
 
-use crate::disas::disas_instruction;
+use crate::disas::{disas_instruction, sprite_line};
 use crate::systems::Chip8State;
 use crate::mem::{Memory16Bit, Chip8Mem};
 
@@ -14,10 +14,15 @@ impl<T: LowerHex + Default + Clone> Backtrace<T> {
     pub fn new(size: usize) -> Self {
         Self { trace: vec![(T::default(), String::new()); size].into_boxed_slice(), cur: 0}
     }
-    pub fn refresh(&mut self, new_val: T, state: Chip8State, cur_op: (u8, u8, u8, u8)) {
+    pub fn refresh(&mut self, new_val: T, state: Chip8State<'_>, cur_op: (u8, u8, u8, u8)) {
         self.cur = (self.cur + 1) % self.trace.len();
         self.trace[self.cur] = (new_val, disas_instruction(cur_op, Some(state)));
     }
+    // From the oldest to the latest
+    pub fn entries(&self) -> impl Iterator<Item = &(T, String)> {
+        let l = self.trace.len();
+        (1..=l).map(move |i| &self.trace[(self.cur + i) % l])
+    }
 }
 
 impl<T: LowerHex + Default + Clone> Display for Backtrace<T> {
@@ -34,14 +39,18 @@ impl<T: LowerHex + Default + Clone> Display for Backtrace<T> {
     }
 }
 
-impl Display for Chip8State {
+impl Display for Chip8State<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = self;
-        let next_instr = s.ram.get(s.pc, 0x2).unwrap();
+        // PC may point past the end of memory after a fault
+        let next_instr = match s.ram.get(s.pc, 0x2) {
+            Ok(op) => format!("0x{:04x}", u16::from_be_bytes([op[0], op[1]])),
+            Err(_) => "????".to_string(),
+        };
         writeln!(f, "\x1b[1mCurrent state : \x1b[0m\n\
                    I : 0x{:03x}  \
                    SP : 0x{:03x}  \
-                   PC : 0x{:03x} -> 0x{21:04x} (next instruction)\n\
+                   PC : 0x{:03x} -> {21} (next instruction)\n\
                    V0 : 0x{:02x}  \
                    V1 : 0x{:02x}  \
                    V2 : 0x{:02x}  \
@@ -63,7 +72,7 @@ impl Display for Chip8State {
                    s.i, s.sp, s.pc,
                    s.v[0], s.v[1], s.v[2],   s.v[3],   s.v[4],   s.v[5],   s.v[6],   s.v[7],
                    s.v[8], s.v[9], s.v[0xA], s.v[0xB], s.v[0xC], s.v[0xD], s.v[0xE], s.v[0xF],
-                   s.delay, s.sound, u16::from_be_bytes([next_instr[0], next_instr[1]]))
+                   s.delay, s.sound, next_instr)
     }
 }
 
@@ -80,3 +89,36 @@ impl Display for Chip8Mem {
         Ok(())
     }
 }
+
+// The display, drawn with the same blocks as sprites, a pixel being lit if it is on any plane
+pub struct Screen<'a>(pub &'a Chip8Mem);
+
+impl Display for Screen<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (width, height) = self.0.disp_size();
+        let row_len = width as usize / 8;
+        let planes: Vec<&[u8]> = (0..self.0.plane_count()).map(|plane| self.0.disp_buf(plane)).collect();
+        for row in 0..height as usize {
+            let line: String = (0..row_len)
+                .map(|col| sprite_line(planes.iter().fold(0, |acc, plane| acc | plane[row * row_len + col])))
+                .collect();
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::systems::{Chip8, Platform, Register, System};
+
+    #[test]
+    fn state_with_pc_at_end_of_memory() {
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        chip8.set_register(Register::Pc, 0xFFE).unwrap();
+        assert!(chip8.get_state().to_string().contains("PC : 0xffe -> 0x0000 (next instruction)"));
+        // As set from the debugger, e.g. with `set pc fff`
+        chip8.set_register(Register::Pc, 0xFFF).unwrap();
+        assert!(chip8.get_state().to_string().contains("PC : 0xfff -> ???? (next instruction)"));
+    }
+}
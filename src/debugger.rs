@@ -0,0 +1,490 @@
+use anyhow::Result;
+
+use crate::{
+    breakpoints::{Breakpoint, Breakpoints},
+    errors::Chip8Fault,
+    mem::Memory16Bit,
+    systems::{Chip8, System},
+    trace::Tracer,
+};
+
+// How the CPU is currently driven
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunMode {
+    Running,
+    Paused,
+    // Stopped on a fault with the `Halt` policy, until the program is restarted
+    Halted,
+    // Executes a single instruction, then pauses
+    Step,
+    // Runs until the subroutine called by the instruction stepped over returns
+    StepOver { return_pc: u16, sp: u16 },
+    // Runs until the current subroutine returns
+    StepOut { sp: u16 },
+}
+
+// What to do when the program faults
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultPolicy {
+    // Stops the CPU for good, for the frontend to show the error
+    Halt,
+    // Skips the faulting instruction, e.g. to run unknown opcodes (like 0NNN machine calls) as NOPs
+    Ignore,
+    // Pauses the CPU on the faulting instruction, for it to be debugged
+    Break,
+    // Restarts the program, from the state given with `set_reset_state`
+    Reset,
+}
+
+impl FaultPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(Self::Halt),
+            "ignore" => Some(Self::Ignore),
+            "break" => Some(Self::Break),
+            "reset" => Some(Self::Reset),
+            _ => None,
+        }
+    }
+}
+
+// Policy for each kind of fault happening while running, halting by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultPolicies {
+    pub stack_overflow: FaultPolicy,
+    pub stack_underflow: FaultPolicy,
+    pub invalid_opcode: FaultPolicy,
+    pub out_of_bounds: FaultPolicy,
+}
+
+impl Default for FaultPolicies {
+    fn default() -> Self {
+        Self {
+            stack_overflow: FaultPolicy::Halt,
+            stack_underflow: FaultPolicy::Halt,
+            invalid_opcode: FaultPolicy::Halt,
+            out_of_bounds: FaultPolicy::Halt,
+        }
+    }
+}
+
+impl FaultPolicies {
+    // Comma separated `kind=policy` pairs, a policy alone applying to every kind, e.g.
+    // `break,invalid-opcode=ignore`. Kinds are stack-overflow, stack-underflow, invalid-opcode and
+    // out-of-bounds, policies halt, ignore, break and reset.
+    pub fn from_name(spec: &str) -> Option<Self> {
+        let mut policies = Self::default();
+        for item in spec.split(',') {
+            match item.split_once('=') {
+                None => {
+                    let policy = FaultPolicy::from_name(item)?;
+                    policies = Self {
+                        stack_overflow: policy,
+                        stack_underflow: policy,
+                        invalid_opcode: policy,
+                        out_of_bounds: policy,
+                    };
+                }
+                Some((kind, policy)) => {
+                    let policy = FaultPolicy::from_name(policy)?;
+                    match kind {
+                        "stack-overflow" => policies.stack_overflow = policy,
+                        "stack-underflow" => policies.stack_underflow = policy,
+                        "invalid-opcode" => policies.invalid_opcode = policy,
+                        "out-of-bounds" => policies.out_of_bounds = policy,
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        Some(policies)
+    }
+
+    pub fn get(&self, fault: Chip8Fault) -> FaultPolicy {
+        match fault {
+            Chip8Fault::StackOverflow { .. } => self.stack_overflow,
+            Chip8Fault::StackUnderflow => self.stack_underflow,
+            Chip8Fault::InvalidOpcode { .. } => self.invalid_opcode,
+            // Only happens while loading programs
+            Chip8Fault::RomTooLarge { .. } => FaultPolicy::Halt,
+            Chip8Fault::OutOfBounds { .. } => self.out_of_bounds,
+        }
+    }
+}
+
+// Drives the CPU instruction by instruction, so that it can be paused anywhere within a frame.
+// Frames are still `cycles_per_frame` instructions long (or up to the display wait), whether they
+// are run at once or stepped through.
+pub struct Debugger {
+    mode: RunMode,
+    // Instructions executed since the last vblank
+    cycles: u32,
+    breakpoints: Breakpoints,
+    // Breakpoint the CPU is paused on, and how many were hit since the start
+    hit: Option<Breakpoint>,
+    hit_count: u64,
+    tracer: Option<Tracer>,
+    fault_policies: FaultPolicies,
+    // Fault the CPU is paused on with the policy applied, and how many paused it since the start
+    fault: Option<(Chip8Fault, FaultPolicy)>,
+    fault_count: u64,
+    reset_state: Option<Vec<u8>>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self {
+            mode: RunMode::Running,
+            cycles: 0,
+            breakpoints: Breakpoints::new(),
+            hit: None,
+            hit_count: 0,
+            tracer: None,
+            fault_policies: FaultPolicies::default(),
+            fault: None,
+            fault_count: 0,
+            reset_state: None,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_mode(&self) -> RunMode {
+        self.mode
+    }
+
+    // Whether nothing runs, halts included
+    pub fn is_paused(&self) -> bool {
+        matches!(self.mode, RunMode::Paused | RunMode::Halted)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.mode == RunMode::Halted
+    }
+
+    pub fn get_breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn get_breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    pub fn get_hit(&self) -> Option<Breakpoint> {
+        self.hit
+    }
+
+    // Increases on each breakpoint hit, for frontends to notice new ones
+    pub fn get_hit_count(&self) -> u64 {
+        self.hit_count
+    }
+
+    // Traces every instruction executed from now on
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn set_fault_policies(&mut self, fault_policies: FaultPolicies) {
+        self.fault_policies = fault_policies;
+    }
+
+    // State to restart from on faults with the `Reset` policy, usually saved right after loading
+    // the program. Those halt instead if none is set.
+    pub fn set_reset_state(&mut self, state: Vec<u8>) {
+        self.reset_state = Some(state);
+    }
+
+    pub fn get_fault(&self) -> Option<(Chip8Fault, FaultPolicy)> {
+        self.fault
+    }
+
+    // Increases on each fault pausing the CPU, for frontends to notice new ones
+    pub fn get_fault_count(&self) -> u64 {
+        self.fault_count
+    }
+
+    // Lets the CPU be resumed after a halt, to be called once the program was restarted
+    pub fn clear_halt(&mut self) {
+        if self.is_halted() {
+            self.fault = None;
+            self.mode = RunMode::Paused;
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if !self.is_halted() {
+            self.mode = RunMode::Paused;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.is_halted() {
+            return;
+        }
+        self.hit = None;
+        self.fault = None;
+        self.mode = RunMode::Running;
+    }
+
+    pub fn step(&mut self) {
+        if self.is_halted() {
+            return;
+        }
+        self.hit = None;
+        self.fault = None;
+        self.mode = RunMode::Step;
+    }
+
+    // Steps over subroutine calls, or a single instruction for any other one
+    pub fn step_over(&mut self, chip8: &Chip8) {
+        if self.is_halted() {
+            return;
+        }
+        let state = chip8.get_state();
+        let is_call = matches!(state.ram.get(state.pc, 2), Ok([opcode, _]) if opcode >> 4 == 0x2);
+        self.hit = None;
+        self.fault = None;
+        self.mode = if is_call {
+            RunMode::StepOver {
+                return_pc: state.pc.wrapping_add(2),
+                sp: state.sp,
+            }
+        } else {
+            RunMode::Step
+        };
+    }
+
+    // Keeps running when not within a subroutine, until something else pauses the CPU
+    pub fn step_out(&mut self, chip8: &Chip8) {
+        if self.is_halted() {
+            return;
+        }
+        self.hit = None;
+        self.fault = None;
+        self.mode = RunMode::StepOut {
+            sp: chip8.get_state().sp,
+        };
+    }
+
+    // Applies the policy of the fault, returning whether the CPU was paused
+    fn handle_fault(&mut self, chip8: &mut Chip8, fault: Chip8Fault) -> Result<bool> {
+        let policy = match self.fault_policies.get(fault) {
+            FaultPolicy::Reset if self.reset_state.is_none() => FaultPolicy::Halt,
+            policy => policy,
+        };
+        match policy {
+            FaultPolicy::Ignore => {
+                chip8.skip_instruction();
+                Ok(false)
+            }
+            FaultPolicy::Reset => {
+                if let Some(state) = &self.reset_state {
+                    chip8.load_state(state)?;
+                }
+                self.cycles = 0;
+                Ok(false)
+            }
+            FaultPolicy::Halt | FaultPolicy::Break => {
+                self.fault = Some((fault, policy));
+                self.fault_count += 1;
+                self.mode = if policy == FaultPolicy::Halt {
+                    RunMode::Halted
+                } else {
+                    RunMode::Paused
+                };
+                Ok(true)
+            }
+        }
+    }
+
+    // Executes an instruction, and the vblank if it ended the frame, returning whether it did.
+    // Faults pausing the CPU leave the frame unfinished.
+    fn exec_instruction(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<bool> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.start_instruction(chip8);
+        }
+        if let Err(err) = chip8.exec_instruction() {
+            let Some(fault) = err.downcast_ref::<Chip8Fault>().copied() else {
+                return Err(err);
+            };
+            if self.handle_fault(chip8, fault)? {
+                return Ok(false);
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.end_instruction(chip8)?;
+        }
+        self.cycles += 1;
+        if self.cycles >= cycles_per_frame || chip8.waits_vblank() {
+            chip8.vblank();
+            self.cycles = 0;
+            if let Some(tracer) = &mut self.tracer {
+                tracer.vblank()?;
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn should_pause(&mut self, chip8: &Chip8) -> bool {
+        if !self.breakpoints.is_empty()
+            && let Some(breakpoint) = self.breakpoints.check(chip8)
+        {
+            self.hit = Some(breakpoint);
+            self.hit_count += 1;
+            return true;
+        }
+        let state = chip8.get_state();
+        match self.mode {
+            RunMode::StepOver { return_pc, sp } => state.pc == return_pc && state.sp == sp,
+            RunMode::StepOut { sp } => state.sp < sp,
+            _ => false,
+        }
+    }
+
+    // To be called in place of `System::run_frame`, executing the rest of the current frame unless
+    // paused or pausing on the way. Returns whether the frame ended, e.g. for movies to move on to
+    // the next one.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<bool> {
+        loop {
+            match self.mode {
+                RunMode::Paused | RunMode::Halted => return Ok(false),
+                RunMode::Step => {
+                    let frame_ended = self.exec_instruction(chip8, cycles_per_frame)?;
+                    // Keeps the breakpoints up to date with the conditions that became true
+                    self.should_pause(chip8);
+                    self.pause();
+                    return Ok(frame_ended);
+                }
+                _ => (),
+            }
+            let frame_ended = self.exec_instruction(chip8, cycles_per_frame)?;
+            // Paused by a fault
+            if self.is_paused() {
+                return Ok(false);
+            }
+            if self.should_pause(chip8) {
+                self.pause();
+                return Ok(frame_ended);
+            }
+            if frame_ended {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        breakpoints::Breakpoint,
+        systems::{CHIP8_DISP_BUF_ADDR, Platform, Register},
+    };
+
+    #[test]
+    fn fault_at_end_of_memory() {
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        // Jumps to the last byte, an instruction there reading past the end of memory
+        chip8.load_program(&[0x1F, 0xFF]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(
+            debugger.get_fault(),
+            Some((Chip8Fault::OutOfBounds { addr: 0xFFF, len: 2 }, FaultPolicy::Halt))
+        );
+        let state = chip8.get_state().to_string();
+        assert!(state.contains("PC : 0xfff -> ???? (next instruction)"), "{state}");
+    }
+
+    #[test]
+    fn display_watchpoints() {
+        // Clears the screen, then draws the font's 0 at (8, 1)
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        chip8.load_program(&[0x00, 0xE0, 0x60, 0x08, 0x61, 0x01, 0xF2, 0x29, 0xD0, 0x15]).unwrap();
+        let mut debugger = Debugger::new();
+        let watch = Breakpoint::parse(&format!("write {CHIP8_DISP_BUF_ADDR:x}")).unwrap();
+        debugger.get_breakpoints_mut().add(watch);
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert_eq!(debugger.get_hit(), Some(watch));
+        assert_eq!(chip8.get_register(Register::Pc), 0x202);
+
+        // The sprite's first line is the second byte of the display's second row
+        let addr = CHIP8_DISP_BUF_ADDR + 9;
+        let watch = Breakpoint::parse(&format!("write {addr:x}")).unwrap();
+        debugger.get_breakpoints_mut().add(watch);
+        debugger.resume();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert_eq!(debugger.get_hit(), Some(watch));
+        assert_eq!(chip8.get_register(Register::Pc), 0x20A);
+        // The frame ended with the drawing, as the display wait quirk wants
+        let writes = chip8.get_mem().get_last_frame_writes();
+        assert!(writes.iter().any(|access| access.addr == addr));
+    }
+
+    #[test]
+    fn halts_until_restarted() {
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        // 0x5001 is invalid
+        chip8.load_program(&[0x50, 0x01]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert!(debugger.is_halted());
+        // Neither continuing nor stepping runs the faulting instruction again
+        debugger.resume();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        debugger.step();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert!(debugger.is_halted());
+        assert_eq!(debugger.get_fault_count(), 1);
+        // Halting again when stepping onto the fault
+        debugger.clear_halt();
+        debugger.step();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert!(debugger.is_halted());
+
+        // Breaks can be continued from
+        let policies = FaultPolicies::from_name("break").unwrap();
+        debugger.set_fault_policies(policies);
+        debugger.clear_halt();
+        assert_eq!(debugger.get_fault(), None);
+        debugger.resume();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert!(debugger.is_paused() && !debugger.is_halted());
+        debugger.resume();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert_eq!(debugger.get_fault_count(), 4);
+    }
+
+    #[test]
+    fn ignored_faults() {
+        let policies = FaultPolicies::from_name("ignore").unwrap();
+        // 0x5001 is invalid, PC wrapping around to 0 after the one at the end of memory
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        chip8.load_program(&[0x1F, 0xFE]).unwrap();
+        chip8.get_mem_mut().set(0xFFE, &[0x50, 0x01]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.set_fault_policies(policies);
+        debugger.step();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        debugger.step();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert_eq!(chip8.get_register(Register::Pc), 0);
+        assert_eq!(debugger.get_fault(), None);
+
+        // The same at the end of the 64K of XO-CHIP
+        let mut chip8 = Chip8::init(Platform::XoChip, Platform::XoChip.default_quirks(), Some(0));
+        chip8.get_mem_mut().set(0xFFFE, &[0x50, 0x01]).unwrap();
+        chip8.set_register(Register::Pc, 0xFFFE).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.set_fault_policies(policies);
+        debugger.step();
+        debugger.run_frame(&mut chip8, 10).unwrap();
+        assert_eq!(chip8.get_register(Register::Pc), 0);
+    }
+}
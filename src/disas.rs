@@ -1,59 +1,70 @@
+use {
+    anyhow::{Result, anyhow},
+    core::fmt,
+    std::collections::{BTreeMap, BTreeSet},
+};
+
 use crate::{
-    mem::Memory16Bit,
-    systems::{Chip8State, CHIP8_STACK_BASE_ADDR}
+    errors::Chip8Fault,
+    mem::{Chip8Mem, Memory16Bit},
+    systems::{Chip8State, CHIP8_PC_START}
 };
 
+// Memory of XO-CHIP, the largest any program can fill
+const MAX_ADDR_SPACE: usize = 0x10000;
+
+pub(crate) fn sprite_line(c: u8) -> String {
+    (0..8).map(|bit| if c & (0x80 >> bit) != 0 {'█'} else {'░'}).collect()
+}
+
 fn sprite_string(sprite: Vec<u8>) -> String {
-    let mut res = String::new();
-    for c in &sprite {
-        res.push_str(
-            String::from_iter([
-                if c & 0b10000000 == 0b10000000 {'█'} else {'░'},
-                if c & 0b01000000 == 0b01000000 {'█'} else {'░'},
-                if c & 0b00100000 == 0b00100000 {'█'} else {'░'},
-                if c & 0b00010000 == 0b00010000 {'█'} else {'░'},
-                if c & 0b00001000 == 0b00001000 {'█'} else {'░'},
-                if c & 0b00000100 == 0b00000100 {'█'} else {'░'},
-                if c & 0b00000010 == 0b00000010 {'█'} else {'░'},
-                if c & 0b00000001 == 0b00000001 {'█'} else {'░'},
-                '\n',
-            ]).as_str()
-         );
-    }
-    if let Some(c) = sprite.last() {
-        res.push_str(
-            String::from_iter([
-                if c & 0b10000000 == 0b10000000 {'█'} else {'░'},
-                if c & 0b01000000 == 0b01000000 {'█'} else {'░'},
-                if c & 0b00100000 == 0b00100000 {'█'} else {'░'},
-                if c & 0b00010000 == 0b00010000 {'█'} else {'░'},
-                if c & 0b00001000 == 0b00001000 {'█'} else {'░'},
-                if c & 0b00000100 == 0b00000100 {'█'} else {'░'},
-                if c & 0b00000010 == 0b00000010 {'█'} else {'░'},
-                if c & 0b00000001 == 0b00000001 {'█'} else {'░'},
-            ]).as_str()
-        );
+    sprite.iter().map(|c| sprite_line(*c)).collect::<Vec<_>>().join("\n")
+}
+
+// Opcode at `addr`, wrapping around the end of memory, or `??` past the end of the program
+fn opcode_string(state: &Chip8State<'_>, addr: u16) -> String {
+    let addr = addr & (state.ram.dump().len() - 1) as u16;
+    match state.ram.get(addr, 2) {
+        Ok(op) => format!("{:x}", u16::from_be_bytes([op[0], op[1]])),
+        Err(_) => "??".to_string(),
     }
-    res
 }
 
-pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) -> String {
+// Which of the 2 instructions after a skip is executed next
+fn skip_string(state: &Chip8State<'_>, skips: bool) -> String {
+    let next = opcode_string(state, state.pc.wrapping_add(2));
+    let after = opcode_string(state, state.pc.wrapping_add(4));
+    let (taken, not_taken) = if skips { (after, next) } else { (next, after) };
+    format!("({skips} → {taken}, avoids {not_taken})")
+}
+
+pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State<'_>>) -> String {
     match opcode {
-        // 0 - return subroutine (RTS) and display clear (CLS)
+        // 0 - return subroutine (RTS), display clear (CLS) and SCHIP display/system control
         (0x0, b, m, l) => {
             match (b, m, l) {
 
+                (0x0, 0xC, n) => format!("SCRD {n:x}"),
+                (0x0, 0xD, n) => format!("SCRU {n:x}"),
+                (0x0, 0xF, 0xB) => "SCRR".to_string(),
+                (0x0, 0xF, 0xC) => "SCRL".to_string(),
+                (0x0, 0xF, 0xD) => "EXIT".to_string(),
+                (0x0, 0xF, 0xE) => "LORES".to_string(),
+                (0x0, 0xF, 0xF) => "HIRES".to_string(),
+
                 (0x0, 0xE, 0x0) => { // CLS
                     "CLS".to_string()
                 },
 
                 (0x0, 0xE, 0xE) => { // RTS
                     if let Some(state) = state {
-                        if state.sp < CHIP8_STACK_BASE_ADDR {
+                        if state.sp < state.platform.stack_base() {
                             return "INVALID RTS from subroutine".to_string();
                         }
-                        let addr_bytes = state.ram.get(state.sp, 2).expect("prolly a stack overflow going on :3");
-                        format!("RTS (→ {:x})", u16::from_be_bytes([addr_bytes[0], addr_bytes[1]]))
+                        match state.ram.get(state.sp, 2) {
+                            Ok(addr_bytes) => format!("RTS (→ {:x})", u16::from_be_bytes([addr_bytes[0], addr_bytes[1]])),
+                            Err(_) => "RTS (→ ??)".to_string(),
+                        }
                     } else {
                         "RTS".to_string()
                     }
@@ -76,11 +87,8 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // 3 - SKIP.EQ direct
         (0x3, x, b, l) => {
             if let Some(state) = state {
-                let next = state.ram.get(state.pc + 2, 4).expect("SKIP.EQ to non-existant instructions");
                 let eq = state.v[x as usize] == (b << 4)+l;
-                let taken = if !eq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                let not_taken = if eq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                format!("SKIP.EQ v{x:X}, {b:x}{l:x} ({eq} → {taken:x}, avoids {not_taken:x})")
+                format!("SKIP.EQ v{x:X}, {b:x}{l:x} {}", skip_string(&state, eq))
             } else {
                 format!("SKIP.EQ v{x:X}, {b:x}{l:x}")
             }
@@ -89,11 +97,8 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // 4 - SKIP.NE direct
         (0x4, x, b, l) => {
             if let Some(state) = state {
-                let next = state.ram.get(state.pc + 2, 4).expect("SKIP.EQ to non-existant instructions");
                 let neq = state.v[x as usize] != (b << 4)+l;
-                let taken = if !neq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                let not_taken = if neq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                format!("SKIP.NE v{x:X}, {b:x}{l:x} ({neq} → {taken:x}, avoids {not_taken:x})")
+                format!("SKIP.NE v{x:X}, {b:x}{l:x} {}", skip_string(&state, neq))
             } else {
                 format!("SKIP.NE v{x:X}, {b:x}{l:x}")
             }
@@ -102,16 +107,17 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // 5 - SKIP.EQ register
         (0x5, x, y, 0x0) => {
             if let Some(state) = state {
-                let next = state.ram.get(state.pc + 2, 4).expect("SKIP.EQ to non-existant instructions");
                 let eq = state.v[x as usize] == state.v[y as usize];
-                let taken = if !eq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                let not_taken = if eq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                format!("SKIP.EQ v{x:X}, v{y:X} ({eq} → {taken:x}, avoids {not_taken:x})")
+                format!("SKIP.EQ v{x:X}, v{y:X} {}", skip_string(&state, eq))
             } else {
                 format!("SKIP.EQ v{x:X}, v{y:X}")
             }
         },
 
+        // 5 - XO-CHIP register range save/load
+        (0x5, x, y, 0x2) => format!("SAVE v{x:X}, v{y:X}"),
+        (0x5, x, y, 0x3) => format!("RESTORE v{x:X}, v{y:X}"),
+
         // 6 - SET direct
         (0x6, x, b, l) => format!("SET v{x:X}, {:x}" ,(b<<4) + l),
 
@@ -152,9 +158,9 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
                 0x7 => { // RSUB
                     if let Some(state) = state {
                         let res = state.v[y as usize].overflowing_sub(state.v[x as usize]);
-                        format!("SUB v{y:X}, v{x:X} → {:x}, vF = {}", res.0, res.1)
+                        format!("RSUB v{x:X}, v{y:X} → {:x}, vF = {}", res.0, res.1)
                     } else {
-                        format!("SUB v{y:X}, v{x:X}")
+                        format!("RSUB v{x:X}, v{y:X}")
                     }
                 },
                 0xE => { // SHL
@@ -173,11 +179,8 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // 9 - SKIP.NE register
         (0x9, x, y, 0x0) => {
             if let Some(state) = state {
-                let next = state.ram.get(state.pc + 2, 4).expect("SKIP.EQ to non-existant instructions");
                 let neq = state.v[x as usize] != state.v[y as usize];
-                let taken = if !neq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                let not_taken = if neq {u16::from_be_bytes([next[0], next[1]])} else {u16::from_be_bytes([next[2], next[3]])};
-                format!("SKIP.NEQ v{x:X}, v{y:X} ({neq} → {taken:x}, avoids {not_taken:x})")
+                format!("SKIP.NEQ v{x:X}, v{y:X} {}", skip_string(&state, neq))
             } else {
                 format!("SKIP.NEQ v{x:X}, v{y:X}")
             }
@@ -189,8 +192,11 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // B - JMP relative
         (0xB, b, m, l) => {
             if let Some(state) = state {
-                let next_pc = (state.v[0] as u16 + u16::from_be_bytes([b , (m << 4) + l])) & 0b0000111111111111;
-                format!("JR v0, {:x} → {:x}",u16::from_be_bytes([b , (m << 4) + l]), next_pc)
+                // SUPER-CHIP's quirk jumps to XNN + VX
+                let reg = if state.quirks.jump_vx { b } else { 0 };
+                let addr_mask = (state.ram.dump().len() - 1) as u16;
+                let next_pc = (state.v[reg as usize] as u16 + u16::from_be_bytes([b , (m << 4) + l])) & addr_mask;
+                format!("JR v{reg:X}, {:x} → {:x}",u16::from_be_bytes([b , (m << 4) + l]), next_pc)
             } else {
                 format!("JR v0, {:x}",u16::from_be_bytes([b , (m << 4) + l]))
             }
@@ -224,15 +230,22 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         // F - MISC things
         (0xF, x, op_b, op_l) => {
             match (x, (op_b << 4) + op_l) {
+                (0x0, 0x00) => "LSETI".to_string(), // LSETI (address in the next 2 bytes)
+                (n, 0x01) => format!("PLANE {n:x}"), // PLANE
+                (0x0, 0x02) => "AUDIO".to_string(), // AUDIO
                 (x, 0x07) => format!("GETD v{x:X}"), // MOVD
                 (x, 0x0A) => format!("WAITKEY v{x:X}"), // WAITKEY
                 (x, 0x15) => format!("SETD v{x:X}"), // RMOVD
                 (x, 0x18) => format!("GETS v{x:X}"), // RMOVS
                 (x, 0x1E) => format!("ADDI v{x:X}"), // ADDI
                 (x, 0x29) => format!("LOADFNT v{x:X}"), // LOADFNT
+                (x, 0x30) => format!("LOADBFNT v{x:X}"), // LOADBFNT
+                (x, 0x3A) => format!("PITCH v{x:X}"), // PITCH
                 (x, 0x33) => format!("DCB v{x:X}"), // DCB
                 (n, 0x55) => format!("STORE {n:X}"), // STORE
                 (n, 0x65) => format!("LOAD {n:X}"), // LOAD
+                (n, 0x75) => format!("SAVEFLAGS {n:X}"), // SAVEFLAGS
+                (n, 0x85) => format!("LOADFLAGS {n:X}"), // LOADFLAGS
                 _ => "INVALID".to_string(),
             }
         }
@@ -240,3 +253,283 @@ pub fn disas_instruction(opcode: (u8, u8, u8, u8), state: Option<Chip8State>) ->
         _ => "INVALID".to_string(),
     }
 }
+
+// Disassembles up to `count` instructions laid out in memory from `addr`
+pub fn disas_mem(ram: &Chip8Mem, addr: u16, count: usize) -> Vec<(u16, String)> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut addr = addr;
+    while instructions.len() < count {
+        let Ok(bytes) = ram.get(addr, 2) else { break };
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let (text, len) = match ram.get(addr.wrapping_add(2), 2) {
+            Ok(long) if opcode == 0xF000 => (format!("LSETI {:x}", u16::from_be_bytes([long[0], long[1]])), 4),
+            _ => (disas_instruction(nibbles(opcode), None), 2),
+        };
+        instructions.push((addr, text));
+        let Some(next) = addr.checked_add(len) else { break };
+        addr = next;
+    }
+    instructions
+}
+
+// Kinds of labels, by priority when an address is targeted in several ways
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Code,
+    Sub,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListingItem {
+    Label(String),
+    Code { addr: u16, bytes: Vec<u8>, text: String },
+    // Bytes never reached by the control flow, most likely sprites and other program data
+    Data { addr: u16, bytes: Vec<u8> },
+}
+
+// Disassembly of a whole program, printed in the dialect of `disas_instruction`, with labels in
+// place of the addresses within the program, for it to be assembled back.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listing {
+    pub items: Vec<ListingItem>,
+}
+
+// Addresses are computed on 32 bits for instructions at the very end of memory not to wrap around
+fn opcode_at(rom: &[u8], addr: u32) -> Option<u16> {
+    let offset = addr.checked_sub(CHIP8_PC_START as u32)? as usize;
+    Some(u16::from_be_bytes([*rom.get(offset)?, *rom.get(offset + 1)?]))
+}
+
+fn nibbles(opcode: u16) -> (u8, u8, u8, u8) {
+    (
+        (opcode >> 12) as u8,
+        (opcode >> 8 & 0xF) as u8,
+        (opcode >> 4 & 0xF) as u8,
+        (opcode & 0xF) as u8,
+    )
+}
+
+// XO-CHIP's F000 NNNN is the only instruction spanning 4 bytes
+fn instruction_len(rom: &[u8], addr: u32) -> u32 {
+    match opcode_at(rom, addr) {
+        Some(0xF000) => 4,
+        _ => 2,
+    }
+}
+
+fn is_skip(opcode: u16) -> bool {
+    matches!(nibbles(opcode), (0x3 | 0x4, _, _, _) | (0x5 | 0x9, _, _, 0x0) | (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1))
+}
+
+// Follows jumps, calls and skips from the program start to find which bytes are instructions,
+// and which addresses are worth a label. Programs must fit in the 64K of XO-CHIP.
+pub fn disassemble_rom(rom: &[u8]) -> Result<Listing> {
+    let max = MAX_ADDR_SPACE - CHIP8_PC_START as usize;
+    if rom.len() > max {
+        return Err(anyhow!(Chip8Fault::RomTooLarge { size: rom.len(), max }));
+    }
+    let rom_end = CHIP8_PC_START as u32 + rom.len() as u32;
+    let in_rom = |addr: u32| (CHIP8_PC_START as u32..rom_end).contains(&addr);
+    let mut code = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let add_label = |labels: &mut BTreeMap<u32, LabelKind>, addr: u32, kind: LabelKind| {
+        if in_rom(addr) && labels.get(&addr).is_none_or(|old| *old < kind) {
+            labels.insert(addr, kind);
+        }
+    };
+
+    let mut to_visit = vec![CHIP8_PC_START as u32];
+    while let Some(mut addr) = to_visit.pop() {
+        while !code.contains_key(&addr) {
+            let Some(opcode) = opcode_at(rom, addr) else { break };
+            if disas_instruction(nibbles(opcode), None) == "INVALID" {
+                break;
+            }
+            let len = instruction_len(rom, addr);
+            if opcode == 0xF000 && opcode_at(rom, addr + 2).is_none() {
+                break;
+            }
+            code.insert(addr, len);
+            let nnn = (opcode & 0xFFF) as u32;
+            match nibbles(opcode) {
+                // RTS, EXIT
+                (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) => break,
+                (0x1, _, _, _) => {
+                    add_label(&mut labels, nnn, LabelKind::Code);
+                    to_visit.push(nnn);
+                    break;
+                }
+                (0x2, _, _, _) => {
+                    add_label(&mut labels, nnn, LabelKind::Sub);
+                    to_visit.push(nnn);
+                }
+                // The actual target depends on v0, but is most often a jump table starting at NNN
+                (0xB, _, _, _) => {
+                    add_label(&mut labels, nnn, LabelKind::Code);
+                    to_visit.push(nnn);
+                    break;
+                }
+                (0xA, _, _, _) => add_label(&mut labels, nnn, LabelKind::Data),
+                (0xF, 0x0, 0x0, 0x0) => {
+                    let target = opcode_at(rom, addr + 2).unwrap_or_default();
+                    add_label(&mut labels, target as u32, LabelKind::Data);
+                }
+                _ if is_skip(opcode) => {
+                    let skipped = addr + 2;
+                    let after = skipped + instruction_len(rom, skipped);
+                    add_label(&mut labels, after, LabelKind::Code);
+                    to_visit.push(after);
+                }
+                _ => (),
+            }
+            addr += len;
+        }
+    }
+
+    let label_name = |addr: u32| {
+        labels.get(&addr).map(|kind| match kind {
+            LabelKind::Data => format!("data_{addr:03x}"),
+            LabelKind::Code => format!("code_{addr:03x}"),
+            LabelKind::Sub => format!("sub_{addr:03x}"),
+        })
+    };
+    let target = |addr: u16| label_name(addr as u32).unwrap_or(format!("{addr:x}"));
+    // Instructions overlapping the next one can't be both assembled back, keep the last as code
+    let starts: BTreeSet<u32> = code.keys().chain(labels.keys()).copied().collect();
+    let next_start = |addr: u32| starts.range(addr + 1..).next().copied().unwrap_or(rom_end);
+
+    let mut listing = Listing::default();
+    let mut addr = CHIP8_PC_START as u32;
+    while addr < rom_end {
+        if let Some(name) = label_name(addr) {
+            listing.items.push(ListingItem::Label(name));
+        }
+        let end = next_start(addr);
+        let offset = |addr: u32| (addr - CHIP8_PC_START as u32) as usize;
+        match code.get(&addr) {
+            Some(len) if addr + len <= end => {
+                let opcode = opcode_at(rom, addr).unwrap_or_default();
+                let text = match nibbles(opcode) {
+                    (0x1, _, _, _) => format!("JMP {}", target(opcode & 0xFFF)),
+                    (0x2, _, _, _) => format!("CALL {}", target(opcode & 0xFFF)),
+                    (0xA, _, _, _) => format!("SETI {}", target(opcode & 0xFFF)),
+                    (0xB, _, _, _) => format!("JR v0, {}", target(opcode & 0xFFF)),
+                    (0xF, 0x0, 0x0, 0x0) => format!("LSETI {}", target(opcode_at(rom, addr + 2).unwrap_or_default())),
+                    op => disas_instruction(op, None),
+                };
+                listing.items.push(ListingItem::Code {
+                    addr: addr as u16,
+                    bytes: rom[offset(addr)..offset(addr + len)].to_vec(),
+                    text,
+                });
+                addr += len;
+            }
+            _ => {
+                // Data pointed at by I is most likely a sprite, shown one line at a time
+                let line_len = if labels.get(&addr) == Some(&LabelKind::Data) { 1 } else { 8 };
+                let mut data_addr = addr;
+                while data_addr < end {
+                    let data_end = end.min(data_addr + line_len);
+                    listing.items.push(ListingItem::Data {
+                        addr: data_addr as u16,
+                        bytes: rom[offset(data_addr)..offset(data_end)].to_vec(),
+                    });
+                    data_addr = data_end;
+                }
+                addr = end;
+            }
+        }
+    }
+    Ok(listing)
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            match item {
+                ListingItem::Label(name) => writeln!(f, "{name}:")?,
+                ListingItem::Code { addr, bytes, text } => {
+                    let bytes: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                    writeln!(f, "    {text:<24}; {addr:03x}: {bytes}")?
+                }
+                ListingItem::Data { addr, bytes } => {
+                    let values = bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(", ");
+                    match bytes.as_slice() {
+                        [byte] => writeln!(f, "    db {values:<21}; {addr:03x}: {}", sprite_line(*byte))?,
+                        _ => writeln!(f, "    db {values:<21}; {addr:03x}")?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Chip8, Platform, Quirks, Register, System};
+
+    fn machine(platform: Platform, quirks: Quirks) -> Chip8 {
+        Chip8::init(platform, quirks, Some(0))
+    }
+
+    #[test]
+    fn instructions_at_end_of_memory() {
+        // The skipped instruction is past the end of memory, or wraps around to 0
+        let mut chip8 = machine(Platform::Chip8, Quirks::VIP);
+        chip8.set_register(Register::Pc, 0xFFC).unwrap();
+        let text = disas_instruction((0x3, 0x0, 0x0, 0x0), Some(chip8.get_state()));
+        assert_eq!(text, "SKIP.EQ v0, 00 (true → 0, avoids 0)");
+        chip8.set_register(Register::Pc, 0xFFD).unwrap();
+        let text = disas_instruction((0x4, 0x0, 0x0, 0x0), Some(chip8.get_state()));
+        assert_eq!(text, "SKIP.NE v0, 00 (false → ??, avoids 0)");
+
+        let mut chip8 = machine(Platform::XoChip, Quirks::XOCHIP);
+        chip8.set_register(Register::Pc, 0xFFFE).unwrap();
+        let text = disas_instruction((0x9, 0x0, 0x1, 0x0), Some(chip8.get_state()));
+        assert_eq!(text, "SKIP.NEQ v0, v1 (false → 0, avoids 0)");
+        let state = Chip8State { sp: 0xFFFF, ..chip8.get_state() };
+        assert_eq!(disas_instruction((0x0, 0x0, 0xE, 0xE), Some(state)), "RTS (→ ??)");
+    }
+
+    #[test]
+    fn stack_of_each_platform() {
+        let mut chip8 = machine(Platform::XoChip, Quirks::XOCHIP);
+        chip8.load_program(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE]).unwrap();
+        chip8.exec_instruction().unwrap();
+        chip8.exec_instruction().unwrap();
+        // Disassembled once returned, the address being popped from the stack
+        let text = disas_instruction((0x0, 0x0, 0xE, 0xE), Some(chip8.get_state()));
+        assert_eq!(text, "RTS (→ 200)");
+        let state = Chip8State { sp: 0xEA0, ..chip8.get_state() };
+        assert_eq!(disas_instruction((0x0, 0x0, 0xE, 0xE), Some(state)), "INVALID RTS from subroutine");
+    }
+
+    #[test]
+    fn relative_jumps() {
+        let mut chip8 = machine(Platform::SChip, Quirks::SCHIP);
+        chip8.set_register(Register::V(0), 1).unwrap();
+        chip8.set_register(Register::V(2), 0x10).unwrap();
+        let text = disas_instruction((0xB, 0x2, 0x3, 0x4), Some(chip8.get_state()));
+        assert_eq!(text, "JR v2, 234 → 244");
+        let text = disas_instruction((0xB, 0xF, 0xF, 0xF), Some(chip8.get_state()));
+        assert_eq!(text, "JR vF, fff → fff");
+
+        let mut chip8 = machine(Platform::XoChip, Quirks::XOCHIP);
+        chip8.set_register(Register::V(0), 0x10).unwrap();
+        let text = disas_instruction((0xB, 0xF, 0xF, 0xF), Some(chip8.get_state()));
+        assert_eq!(text, "JR v0, fff → 100f");
+        assert_eq!(disas_instruction((0xB, 0xF, 0xF, 0xF), None), "JR v0, fff");
+    }
+
+    #[test]
+    fn disassemble_rom_filling_memory() {
+        // The last instruction sits at 0xFFFE
+        let rom = [0x60, 0x00].repeat((MAX_ADDR_SPACE - CHIP8_PC_START as usize) / 2);
+        let listing = disassemble_rom(&rom).unwrap();
+        assert!(matches!(listing.items.last(), Some(ListingItem::Code { addr: 0xFFFE, .. })));
+        assert!(disassemble_rom(&[rom, vec![0x00]].concat()).is_err());
+    }
+}
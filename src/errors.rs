@@ -1,12 +1,176 @@
 use core::fmt;
 use std::error::Error;
 
+// Faults the emulated machine can run into, structured for frontends to react to each of them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Fault {
+    // A subroutine was called with the whole stack already in use
+    StackOverflow { sp: u16 },
+    // A subroutine return was executed outside of any subroutine
+    StackUnderflow,
+    // The opcode doesn't exist, or not on the emulated platform
+    InvalidOpcode { pc: u16, opcode: u16 },
+    // An access to `len` bytes from `addr` goes past the end of memory
+    OutOfBounds { addr: u16, len: u16 },
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for Chip8Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Chip8Fault : ")?;
+        match self {
+            Self::StackOverflow { sp } => write!(f, "stack overflow (SP 0x{sp:03X})"),
+            Self::StackUnderflow => write!(f, "stack underflow, returned from the main routine"),
+            Self::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode 0x{opcode:04X} at 0x{pc:03X}")
+            }
+            Self::OutOfBounds { addr, len } => {
+                write!(f, "access to 0x{len:X} bytes from 0x{addr:03X} out of memory bounds")
+            }
+            Self::RomTooLarge { size, max } => write!(f, "program too long, {size} B > {max} B"),
+        }
+    }
+}
+impl Error for Chip8Fault {}
+
+#[derive(Debug)]
+pub struct SaveStateError {
+    reason: String,
+}
+
+impl SaveStateError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SaveStateError : {}", self.reason)
+    }
+}
+impl Error for SaveStateError {}
+
+#[derive(Debug)]
+pub struct MovieError {
+    reason: String,
+}
+
+impl MovieError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MovieError : {}", self.reason)
+    }
+}
+impl Error for MovieError {}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    reason: String,
+}
+
+impl ConfigError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConfigError : {}", self.reason)
+    }
+}
+impl Error for ConfigError {}
+
+#[derive(Debug)]
+pub struct AsmError {
+    reason: String,
+}
+
+impl AsmError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AsmError : {}", self.reason)
+    }
+}
+impl Error for AsmError {}
+
+#[derive(Debug)]
+pub struct BreakpointError {
+    reason: String,
+}
+
+impl BreakpointError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for BreakpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BreakpointError : {}", self.reason)
+    }
+}
+impl Error for BreakpointError {}
+
+#[derive(Debug)]
+pub struct GdbError {
+    reason: String,
+}
+
+impl GdbError {
+    pub fn new<T>(reason: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GdbError : {}", self.reason)
+    }
+}
+impl Error for GdbError {}
+
 #[derive(Debug)]
-pub struct InvalidAccessError {
+pub struct DatabaseError {
     reason: String,
 }
 
-impl InvalidAccessError {
+impl DatabaseError {
     pub fn new<T>(reason: T) -> Self
     where
         T: ToString,
@@ -16,19 +180,19 @@ impl InvalidAccessError {
         }
     }
 }
-impl fmt::Display for InvalidAccessError {
+impl fmt::Display for DatabaseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "InvalidAccessError : {}", self.reason)
+        write!(f, "DatabaseError : {}", self.reason)
     }
 }
-impl Error for InvalidAccessError {}
+impl Error for DatabaseError {}
 
 #[derive(Debug)]
-pub struct InvalidInstructionError {
+pub struct TestRomError {
     reason: String,
 }
 
-impl InvalidInstructionError {
+impl TestRomError {
     pub fn new<T>(reason: T) -> Self
     where
         T: ToString,
@@ -38,19 +202,19 @@ impl InvalidInstructionError {
         }
     }
 }
-impl fmt::Display for InvalidInstructionError {
+impl fmt::Display for TestRomError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "InvalidInstructionError : {}", self.reason)
+        write!(f, "TestRomError : {}", self.reason)
     }
 }
-impl Error for InvalidInstructionError {}
+impl Error for TestRomError {}
 
 #[derive(Debug)]
-pub struct ProgramLoadingError {
+pub struct RegisterError {
     reason: String,
 }
 
-impl ProgramLoadingError {
+impl RegisterError {
     pub fn new<T>(reason: T) -> Self
     where
         T: ToString,
@@ -60,9 +224,9 @@ impl ProgramLoadingError {
         }
     }
 }
-impl fmt::Display for ProgramLoadingError {
+impl fmt::Display for RegisterError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ProgramLoadingError : {}", self.reason)
+        write!(f, "RegisterError : {}", self.reason)
     }
 }
-impl Error for ProgramLoadingError {}
+impl Error for RegisterError {}
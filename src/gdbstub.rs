@@ -0,0 +1,674 @@
+use {
+    anyhow::{Result, anyhow},
+    log::info,
+    std::{
+        io::{ErrorKind, Read, Write},
+        net::{TcpListener, TcpStream, ToSocketAddrs},
+        sync::{
+            Arc, Mutex, MutexGuard, RwLock,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::Duration,
+    },
+};
+
+use crate::{
+    breakpoints::{Breakpoint, WatchKind},
+    debugger::Debugger,
+    errors::{Chip8Fault, GdbError},
+    mem::Memory16Bit,
+    systems::{Chip8, Register},
+};
+
+// How often the connection is polled for an interrupt while the CPU runs
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const PACKET_SIZE: usize = 0x1000;
+
+// GDB register numbers : v0-vF, then i, pc, sp, dt and st. 16-bit registers are sent big-endian,
+// like words in the CHIP-8 memory.
+const REGISTER_COUNT: usize = 21;
+
+fn register(regnum: usize) -> Option<(Register, usize)> {
+    match regnum {
+        0..=15 => Some((Register::V(regnum as u8), 1)),
+        16 => Some((Register::I, 2)),
+        17 => Some((Register::Pc, 2)),
+        18 => Some((Register::Sp, 2)),
+        19 => Some((Register::Delay, 1)),
+        20 => Some((Register::Sound, 1)),
+        _ => None,
+    }
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.rusty-chip8.cpu\">",
+    );
+    for (regnum, (reg, size)) in (0..REGISTER_COUNT).filter_map(|n| Some((n, register(n)?))) {
+        let kind = match reg {
+            Register::Pc => "code_ptr",
+            Register::I | Register::Sp => "data_ptr",
+            _ => "uint8",
+        };
+        xml += &format!(
+            "<reg name=\"{reg}\" bitsize=\"{}\" type=\"{kind}\" regnum=\"{regnum}\"/>",
+            size * 8
+        );
+    }
+    xml + "</feature></target>"
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+// `addr,len` as found in memory and breakpoint packets
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+enum Incoming {
+    Packet(String),
+    // Ctrl-C, sent out of any packet
+    Interrupt,
+}
+
+struct Connection {
+    stream: TcpStream,
+    // Bytes received but not yet part of a complete packet
+    pending: Vec<u8>,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Self {
+            stream,
+            pending: Vec::new(),
+            no_ack: false,
+        })
+    }
+
+    // Takes the next packet or interrupt out of the pending bytes, skipping acks
+    fn take_pending(&mut self) -> Result<Option<Incoming>> {
+        loop {
+            let Some(start) = self.pending.iter().position(|&b| b == b'$' || b == 0x03) else {
+                self.pending.clear();
+                return Ok(None);
+            };
+            self.pending.drain(..start);
+            if self.pending[0] == 0x03 {
+                self.pending.remove(0);
+                return Ok(Some(Incoming::Interrupt));
+            }
+            let Some(end) = self.pending.iter().position(|&b| b == b'#') else {
+                return Ok(None);
+            };
+            if self.pending.len() < end + 3 {
+                return Ok(None);
+            }
+            let data = self.pending[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.pending[end + 1..end + 3])
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            self.pending.drain(..end + 3);
+            if !self.no_ack {
+                let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+                if checksum != Some(sum) {
+                    // Asks for a retransmission
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(Incoming::Packet(
+                String::from_utf8_lossy(&data).into_owned(),
+            )));
+        }
+    }
+
+    // Returns `None` if nothing complete came in within the poll interval
+    fn poll(&mut self) -> Result<Option<Incoming>> {
+        if let Some(incoming) = self.take_pending()? {
+            return Ok(Some(incoming));
+        }
+        let mut buf = [0; 1024];
+        match self.stream.read(&mut buf) {
+            Ok(0) => Err(anyhow!(GdbError::new("connection closed"))),
+            Ok(len) => {
+                self.pending.extend_from_slice(&buf[..len]);
+                self.take_pending()
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Replies aren't retransmitted, TCP already being reliable
+    fn send(&mut self, data: &str) -> Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream
+            .write_all(format!("${data}#{checksum:02x}").as_bytes())?;
+        Ok(())
+    }
+}
+
+enum Reply {
+    Now(String),
+    // The CPU was resumed, the reply being sent once it stops
+    Resumed,
+    Detach,
+    Kill,
+}
+
+fn error_reply() -> Reply {
+    Reply::Now("E01".to_string())
+}
+
+// GDB remote serial protocol server, controlling the CPU through the same debugger as the GUI :
+// the frontend keeps running frames, while the stub pauses, steps, resumes it and sets breakpoints.
+pub struct GdbStub {
+    chip8: Arc<RwLock<Chip8>>,
+    debugger: Arc<Mutex<Debugger>>,
+    // Whether gdb paused the CPU with Ctrl-C since it was last resumed
+    interrupted: AtomicBool,
+}
+
+impl GdbStub {
+    pub fn new(chip8: Arc<RwLock<Chip8>>, debugger: Arc<Mutex<Debugger>>) -> Self {
+        Self {
+            chip8,
+            debugger,
+            interrupted: AtomicBool::new(false),
+        }
+    }
+
+    fn lock_debugger(&self) -> MutexGuard<'_, Debugger> {
+        self.debugger.lock().expect("Lock poisoned")
+    }
+
+    // Serves clients one after the other, forever
+    pub fn listen(&self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Waiting for gdb on {}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            info!("gdb connected from {}", stream.peer_addr()?);
+            if let Err(err) = self.serve(stream) {
+                info!("gdb disconnected : {err}");
+            }
+        }
+        Ok(())
+    }
+
+    // Serves a single client until it detaches, kills the program or disconnects, leaving the
+    // CPU running afterwards
+    pub fn serve(&self, stream: TcpStream) -> Result<()> {
+        let mut conn = Connection::new(stream)?;
+        // GDB expects the target to be stopped once attached
+        self.lock_debugger().pause();
+        self.interrupted.store(false, Ordering::Relaxed);
+        let res = self.serve_packets(&mut conn);
+        self.lock_debugger().resume();
+        res
+    }
+
+    fn serve_packets(&self, conn: &mut Connection) -> Result<()> {
+        loop {
+            let Some(Incoming::Packet(packet)) = conn.poll()? else {
+                continue;
+            };
+            let reply = match self.handle(&packet) {
+                Reply::Now(reply) => reply,
+                Reply::Resumed => self.wait_stop(conn)?,
+                Reply::Detach => return conn.send("OK"),
+                Reply::Kill => return Ok(()),
+            };
+            conn.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                conn.no_ack = true;
+            }
+        }
+    }
+
+    // Waits for the CPU to pause, on a breakpoint, after a step or on an interrupt from gdb
+    fn wait_stop(&self, conn: &mut Connection) -> Result<String> {
+        loop {
+            let debugger = self.lock_debugger();
+            if debugger.is_paused() {
+                return Ok(self.stop_reply(&debugger));
+            }
+            drop(debugger);
+            if let Some(Incoming::Interrupt) = conn.poll()? {
+                self.interrupted.store(true, Ordering::Relaxed);
+                self.lock_debugger().pause();
+            }
+        }
+    }
+
+    // Signal the CPU is paused with : SIGINT on Ctrl-C, SIGILL or SIGSEGV on faults, SIGTRAP otherwise
+    fn stop_reply(&self, debugger: &Debugger) -> String {
+        match (debugger.get_hit(), debugger.get_fault()) {
+            _ if self.interrupted.load(Ordering::Relaxed) => "S02".to_string(),
+            (_, Some((Chip8Fault::InvalidOpcode { .. }, _))) => "S04".to_string(),
+            (_, Some(_)) => "S0b".to_string(),
+            (Some(Breakpoint::Watch { start, kind, .. }), _) => {
+                let kind = match kind {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write => "watch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T05{kind}:{start:x};")
+            }
+            _ => "S05".to_string(),
+        }
+    }
+
+    fn handle(&self, packet: &str) -> Reply {
+        let Some(cmd) = packet.chars().next() else {
+            return Reply::Now(String::new());
+        };
+        // Malformed packets may start with a replacement character, longer than a byte
+        let args = &packet[cmd.len_utf8()..];
+        let reply = match cmd {
+            '?' => Some(self.stop_reply(&self.lock_debugger())),
+            'g' => Some(self.read_registers()),
+            'G' => self.write_registers(args),
+            'p' => usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|regnum| self.read_register(regnum)),
+            'P' => args.split_once('=').and_then(|(regnum, value)| {
+                self.write_register(usize::from_str_radix(regnum, 16).ok()?, value)
+            }),
+            'm' => parse_range(args).and_then(|(addr, len)| self.read_memory(addr, len)),
+            'M' => args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = parse_range(range)?;
+                self.write_memory(addr, len, data)
+            }),
+            'c' | 's' => return self.resume(args, cmd == 's').unwrap_or_else(error_reply),
+            'Z' | 'z' => self.set_breakpoint(args, cmd == 'Z'),
+            'H' | 'T' => Some("OK".to_string()),
+            'D' => return Reply::Detach,
+            'k' => return Reply::Kill,
+            'q' | 'Q' | 'v' => return self.handle_named(packet),
+            _ => Some(String::new()),
+        };
+        reply.map_or_else(error_reply, Reply::Now)
+    }
+
+    fn handle_named(&self, packet: &str) -> Reply {
+        let reply = match packet.split_once(':').map_or(packet, |(name, _)| name) {
+            "qSupported" => {
+                format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+")
+            }
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qXfer" => match packet.strip_prefix("qXfer:features:read:target.xml:") {
+                Some(range) => {
+                    let Some((offset, len)) = range.split_once(',').and_then(|(offset, len)| {
+                        Some((
+                            usize::from_str_radix(offset, 16).ok()?,
+                            usize::from_str_radix(len, 16).ok()?,
+                        ))
+                    }) else {
+                        return error_reply();
+                    };
+                    let xml = target_xml();
+                    let chunk = xml.get(offset..).unwrap_or_default();
+                    if chunk.len() > len {
+                        format!("m{}", &chunk[..len])
+                    } else {
+                        format!("l{chunk}")
+                    }
+                }
+                None => String::new(),
+            },
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            // Only one thread : the first action applies
+            _ if packet.starts_with("vCont;") => {
+                return match packet[6..].chars().next() {
+                    Some('c' | 'C') => self.resume("", false).unwrap_or_else(error_reply),
+                    Some('s' | 'S') => self.resume("", true).unwrap_or_else(error_reply),
+                    _ => error_reply(),
+                };
+            }
+            _ => String::new(),
+        };
+        Reply::Now(reply)
+    }
+
+    // Continues or steps, from the given address if any
+    fn resume(&self, addr: &str, step: bool) -> Option<Reply> {
+        let mut debugger = self.lock_debugger();
+        self.interrupted.store(false, Ordering::Relaxed);
+        if !addr.is_empty() {
+            let addr = parse_hex(addr)?;
+            self.chip8
+                .write()
+                .expect("Lock poisoned")
+                .set_register(Register::Pc, addr)
+                .ok()?;
+        }
+        if step {
+            debugger.step();
+        } else {
+            debugger.resume();
+        }
+        Some(Reply::Resumed)
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .filter_map(|regnum| self.read_register(regnum))
+            .collect()
+    }
+
+    fn write_registers(&self, hex: &str) -> Option<String> {
+        let bytes = from_hex(hex)?;
+        let mut offset = 0;
+        let mut values = Vec::new();
+        for regnum in 0..REGISTER_COUNT {
+            let (reg, size) = register(regnum)?;
+            let value = bytes.get(offset..offset + size)?;
+            values.push((reg, value.iter().fold(0, |acc, b| acc << 8 | *b as u16)));
+            offset += size;
+        }
+        // Nothing is written when any of the registers can't be
+        let mut chip8 = self.chip8.write().expect("Lock poisoned");
+        let stack = chip8.get_platform().stack_range();
+        if values
+            .iter()
+            .any(|(reg, value)| *reg == Register::Sp && !stack.contains(value))
+        {
+            return None;
+        }
+        for (reg, value) in values {
+            chip8.set_register(reg, value).ok()?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_register(&self, regnum: usize) -> Option<String> {
+        let (reg, size) = register(regnum)?;
+        let value = self.chip8.read().expect("Lock poisoned").get_register(reg);
+        Some(to_hex(&value.to_be_bytes()[2 - size..]))
+    }
+
+    fn write_register(&self, regnum: usize, hex: &str) -> Option<String> {
+        let (reg, size) = register(regnum)?;
+        let value = from_hex(hex).filter(|value| value.len() == size)?;
+        self.chip8
+            .write()
+            .expect("Lock poisoned")
+            .set_register(reg, value.iter().fold(0, |acc, b| acc << 8 | *b as u16))
+            .ok()?;
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&self, addr: u16, len: u16) -> Option<String> {
+        let chip8 = self.chip8.read().expect("Lock poisoned");
+        let mem = chip8.get_mem();
+        let len = len.min(PACKET_SIZE as u16 / 2);
+        // Reads crossing the end of memory are cut short, as gdb expects
+        let len = len.min((mem.dump().len() as u32).saturating_sub(addr as u32) as u16);
+        if len == 0 {
+            return None;
+        }
+        mem.get(addr, len).ok().map(to_hex)
+    }
+
+    fn write_memory(&self, addr: u16, len: u16, hex: &str) -> Option<String> {
+        let data = from_hex(hex).filter(|data| data.len() == len as usize)?;
+        let mut chip8 = self.chip8.write().expect("Lock poisoned");
+        chip8.get_mem_mut().set(addr, &data).ok()?;
+        Some("OK".to_string())
+    }
+
+    // Software and hardware breakpoints are the same, as well as watchpoints in their 3 kinds
+    fn set_breakpoint(&self, args: &str, insert: bool) -> Option<String> {
+        let (kind, range) = args.split_once(',')?;
+        let (addr, len) = parse_range(range)?;
+        let watch = |kind| Breakpoint::Watch {
+            start: addr,
+            end: addr.saturating_add(len.max(1) - 1),
+            kind,
+        };
+        let breakpoint = match kind {
+            "0" | "1" => Breakpoint::Pc(addr),
+            "2" => watch(WatchKind::Write),
+            "3" => watch(WatchKind::Read),
+            "4" => watch(WatchKind::Access),
+            _ => return Some(String::new()),
+        };
+        let mut debugger = self.lock_debugger();
+        let breakpoints = debugger.get_breakpoints_mut();
+        if insert {
+            breakpoints.add(breakpoint);
+        } else if let Some(index) = breakpoints.get().iter().position(|bp| *bp == breakpoint) {
+            breakpoints.remove(index);
+        }
+        Some("OK".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Platform, Quirks, System};
+
+    // Connection to a client socket, which acks are read from
+    fn connect() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        (Connection::new(stream).unwrap(), client)
+    }
+
+    fn read_acks(client: &mut TcpStream, len: usize) -> String {
+        let mut acks = vec![0; len];
+        client.read_exact(&mut acks).unwrap();
+        String::from_utf8(acks).unwrap()
+    }
+
+    fn packet(incoming: Option<Incoming>) -> Option<String> {
+        match incoming {
+            Some(Incoming::Packet(packet)) => Some(packet),
+            _ => None,
+        }
+    }
+
+    fn stub() -> GdbStub {
+        let chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        GdbStub::new(Arc::new(RwLock::new(chip8)), Arc::new(Mutex::new(Debugger::new())))
+    }
+
+    fn reply(stub: &GdbStub, packet: &str) -> Option<String> {
+        match stub.handle(packet) {
+            Reply::Now(reply) => Some(reply),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        let (mut conn, mut client) = connect();
+        conn.pending.extend_from_slice(b"+$g#67$m200,2#ff$m200,2#5d");
+        assert_eq!(packet(conn.take_pending().unwrap()).as_deref(), Some("g"));
+        // The packet with a wrong checksum is dropped, for gdb to send it again
+        assert_eq!(packet(conn.take_pending().unwrap()).as_deref(), Some("m200,2"));
+        assert_eq!(read_acks(&mut client, 3), "+-+");
+        assert!(conn.take_pending().unwrap().is_none());
+    }
+
+    #[test]
+    fn partial_packets() {
+        let (mut conn, mut client) = connect();
+        conn.pending.extend_from_slice(b"$g#6");
+        assert!(conn.take_pending().unwrap().is_none());
+        conn.pending.extend_from_slice(b"7");
+        assert_eq!(packet(conn.take_pending().unwrap()).as_deref(), Some("g"));
+        assert_eq!(read_acks(&mut client, 1), "+");
+    }
+
+    #[test]
+    fn interrupts_and_no_ack_mode() {
+        let (mut conn, _client) = connect();
+        conn.no_ack = true;
+        conn.pending.extend_from_slice(b"\x03$g#00");
+        assert!(matches!(conn.take_pending().unwrap(), Some(Incoming::Interrupt)));
+        assert_eq!(packet(conn.take_pending().unwrap()).as_deref(), Some("g"));
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let stub = stub();
+        let lossy = String::from_utf8_lossy(b"\xffm200,2").into_owned();
+        let packets = [
+            lossy.as_str(),
+            "\u{e9}",
+            "m",
+            "m200",
+            "mzz,2",
+            "M200,2:1",
+            "p",
+            "pzz",
+            "P0=",
+            "G00",
+            "Z0",
+            "Z9,200,2",
+            "c1x",
+        ];
+        for packet in packets {
+            assert!(reply(&stub, packet).is_some(), "{packet}");
+        }
+        assert_eq!(reply(&stub, "m200,2").as_deref(), Some("0000"));
+        assert_eq!(reply(&stub, "m200").as_deref(), Some("E01"));
+        assert_eq!(reply(&stub, "\u{e9}").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn stack_pointer_writes() {
+        let stub = stub();
+        // V0 to VF, I, PC, SP, then the timers
+        let registers = |v0: &str, sp: &str| format!("{v0}{}00000200{sp}0000", "00".repeat(15));
+        assert_eq!(reply(&stub, "P12=0000").as_deref(), Some("E01"));
+        assert_eq!(reply(&stub, &format!("G{}", registers("01", "0000"))).as_deref(), Some("E01"));
+        assert_eq!(reply(&stub, "p0").as_deref(), Some("00"));
+        assert_eq!(reply(&stub, &format!("G{}", registers("01", "0ea2"))).as_deref(), Some("OK"));
+        assert_eq!(reply(&stub, "p12").as_deref(), Some("0ea2"));
+        assert_eq!(reply(&stub, "P12=0f00").as_deref(), Some("OK"));
+        assert_eq!(reply(&stub, "p0").as_deref(), Some("01"));
+    }
+
+    // Sends a packet as gdb would, returning the reply without its framing
+    fn exchange(client: &mut TcpStream, data: &[u8]) -> String {
+        let checksum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        client.write_all(b"$").unwrap();
+        client.write_all(data).unwrap();
+        client.write_all(format!("#{checksum:02x}").as_bytes()).unwrap();
+        read_reply(client)
+    }
+
+    fn read_reply(client: &mut TcpStream) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+            client.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+        let start = reply.iter().position(|&b| b == b'$').unwrap();
+        String::from_utf8(reply[start + 1..reply.len() - 3].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn session() {
+        // V0 = 1, then V0 += 1 forever
+        let mut chip8 = Chip8::init(Platform::Chip8, Quirks::VIP, Some(0));
+        chip8.load_program(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        let chip8 = Arc::new(RwLock::new(chip8));
+        let debugger = Arc::new(Mutex::new(Debugger::new()));
+        debugger.lock().unwrap().pause();
+        let stub = GdbStub::new(chip8.clone(), debugger.clone());
+
+        // Frames keep being run meanwhile, as by the frontend
+        let done = Arc::new(AtomicBool::new(false));
+        let frontend = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let mut chip8 = chip8.write().unwrap();
+                    debugger.lock().unwrap().run_frame(&mut chip8, 10).unwrap();
+                    drop(chip8);
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stub.serve(stream)
+        });
+
+        assert_eq!(exchange(&mut client, b"?"), "S05");
+        let registers = exchange(&mut client, b"g");
+        assert_eq!(registers.len(), 48);
+        // PC after V0 to VF and I
+        assert_eq!(&registers[36..40], "0200");
+        assert_eq!(exchange(&mut client, b"m200,6"), "600170011202");
+
+        assert_eq!(exchange(&mut client, b"Z0,204,2"), "OK");
+        assert_eq!(exchange(&mut client, b"c"), "S05");
+        assert_eq!(exchange(&mut client, b"p11"), "0204");
+        assert_eq!(exchange(&mut client, b"p0"), "02");
+        assert_eq!(exchange(&mut client, b"z0,204,2"), "OK");
+        assert_eq!(exchange(&mut client, b"s"), "S05");
+        assert_eq!(exchange(&mut client, b"p11"), "0202");
+
+        // Watchpoints report the address, Ctrl-C and faults their own signals
+        assert_eq!(exchange(&mut client, b"Z2,300,1"), "OK");
+        assert_eq!(exchange(&mut client, b"M204,4:a300f055"), "OK");
+        assert_eq!(exchange(&mut client, b"c"), "T05watch:300;");
+        assert_eq!(exchange(&mut client, b"?"), "T05watch:300;");
+        assert_eq!(exchange(&mut client, b"z2,300,1"), "OK");
+        assert_eq!(exchange(&mut client, b"M208,2:1202"), "OK");
+        client.write_all(b"$c#63").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        client.write_all(b"\x03").unwrap();
+        assert_eq!(read_reply(&mut client), "S02");
+        assert_eq!(exchange(&mut client, b"?"), "S02");
+        assert_eq!(exchange(&mut client, b"M202,2:5001"), "OK");
+        assert_eq!(exchange(&mut client, b"c200"), "S04");
+        assert_eq!(exchange(&mut client, b"?"), "S04");
+
+        client.write_all(b"$k#6b").unwrap();
+        server.join().unwrap().unwrap();
+        done.store(true, Ordering::Relaxed);
+        frontend.join().unwrap();
+    }
+}
@@ -1,9 +1,45 @@
-use egui::{ClippedPrimitive, Context, TexturesDelta, ViewportId};
+use egui::{ClippedPrimitive, Context, RichText, TexturesDelta, ViewportId};
 use egui_wgpu::{Renderer, ScreenDescriptor};
+use anyhow::{Result, anyhow};
 use pixels::{wgpu, PixelsContext};
+use rusty_chip8::{
+    breakpoints::Breakpoint,
+    debugger::{Debugger, FaultPolicy},
+    disas::disas_mem,
+    errors::ConfigError,
+    mem::Memory16Bit,
+    systems::{
+        CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN, CHIP8_FONT_START, CHIP8_PC_START, CHIP8_STACK_LEN,
+        Chip8, MemoryIncrement, Platform, Quirks, SCHIP_BIG_FONT_HEIGHT, SCHIP_BIG_FONT_START,
+    },
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+use winit::dpi::PhysicalSize;
 use winit::event_loop::EventLoopWindowTarget;
 use winit::window::Window;
 
+use crate::{
+    keymap::{KeyMap, key_from_name, key_name},
+    loader::Loader,
+    settings::{MachineSettings, Settings},
+};
+
+// Instructions shown before and after the current one in the debugger
+const DISAS_LINES_BEFORE: u16 = 4;
+const DISAS_LINES: usize = 16;
+// Bytes per line of the memory viewer
+const MEM_ROW_LEN: usize = 0x10;
+const MEM_COLOR_FONT: egui::Color32 = egui::Color32::LIGHT_BLUE;
+const MEM_COLOR_PROGRAM: egui::Color32 = egui::Color32::LIGHT_GREEN;
+const MEM_COLOR_STACK: egui::Color32 = egui::Color32::GOLD;
+const MEM_COLOR_DISPLAY: egui::Color32 = egui::Color32::KHAKI;
+const MEM_COLOR_WRITTEN: egui::Color32 = egui::Color32::DARK_RED;
+// Files listed by the file browser, unless all of them are shown
+const PROGRAM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "8o"];
+
 // Manages all state required for rendering egui over `Pixels`.
 pub(crate) struct Framework {
     // State for egui.
@@ -24,16 +60,51 @@ struct Gui {
     // TODO: implement gui features and "clean" code
     debug_win_open: bool,
     mem_win_open: bool,
+    chip8: Arc<RwLock<Chip8>>,
+    debugger: Arc<Mutex<Debugger>>,
+    loader: Arc<Mutex<Loader>>,
+    settings: Arc<RwLock<Settings>>,
+    info_win_open: bool,
+    prefs_win_open: bool,
+    // Settings being edited, applied when saved
+    prefs: Settings,
+    // Host keys bound to each hex key, as typed
+    prefs_keys: [String; 0x10],
+    // Name and SHA-1 of the program whose settings are edited
+    prefs_program: Option<(String, String)>,
+    prefs_error: Option<String>,
+    // Error of the last program opened, shown until dismissed
+    load_error: Option<String>,
+    open_win_open: bool,
+    browser_dir: PathBuf,
+    browser_show_all: bool,
+    // Subdirectories and files of `browser_dir`, read when it changes
+    browser_entries: Vec<(PathBuf, bool)>,
+    breakpoint_input: String,
+    breakpoint_error: Option<String>,
+    // Breakpoint hits already shown, the debugger opening on new ones
+    seen_hit_count: u64,
+    // Same for faults, which open the debugger or the fault window depending on their policy
+    seen_fault_count: u64,
+    fault_win_open: bool,
+    mem_goto_input: String,
+    // Row the memory viewer has to scroll to on its next frame
+    mem_scroll_to: Option<usize>,
+    // Byte being edited, and its new value
+    mem_selected: Option<u16>,
+    mem_edit_input: String,
 }
 
 impl Framework {
     // Create egui.
     pub(crate) fn new<T>(
         event_loop: &EventLoopWindowTarget<T>,
-        width: u32,
-        height: u32,
+        window_size: PhysicalSize<u32>,
         scale_factor: f32,
         pixels: &pixels::Pixels,
+        chip8: Arc<RwLock<Chip8>>,
+        debugger: Arc<Mutex<Debugger>>,
+        loader: Arc<Mutex<Loader>>,
     ) -> Self {
         let max_texture_size = pixels.device().limits().max_texture_dimension_2d as usize;
 
@@ -46,12 +117,12 @@ impl Framework {
             Some(max_texture_size),
         );
         let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [width, height],
+            size_in_pixels: [window_size.width, window_size.height],
             pixels_per_point: scale_factor,
         };
         let renderer = Renderer::new(pixels.device(), pixels.render_texture_format(), None, 1);
         let textures = TexturesDelta::default();
-        let gui = Gui::new();
+        let gui = Gui::new(chip8, debugger, loader);
 
         Self {
             egui_ctx,
@@ -149,27 +220,652 @@ impl Framework {
 
 impl Gui {
     // Create a `Gui`.
-    fn new() -> Self {
+    fn new(chip8: Arc<RwLock<Chip8>>, debugger: Arc<Mutex<Debugger>>, loader: Arc<Mutex<Loader>>) -> Self {
+        let settings = loader.lock().expect("Lock poisoned").get_settings();
+        // The file browser starts next to the current program
+        let browser_dir = loader
+            .lock()
+            .expect("Lock poisoned")
+            .get_path()
+            .and_then(|path| std::fs::canonicalize(path).ok())
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
         Self {
             about_win_open: false,
             debug_win_open: false,
             mem_win_open: false,
+            chip8,
+            debugger,
+            loader,
+            info_win_open: false,
+            prefs_win_open: false,
+            prefs: Settings::default(),
+            prefs_keys: Default::default(),
+            prefs_program: None,
+            prefs_error: None,
+            settings,
+            load_error: None,
+            open_win_open: false,
+            browser_dir,
+            browser_show_all: false,
+            browser_entries: Vec::new(),
+            breakpoint_input: String::new(),
+            breakpoint_error: None,
+            seen_hit_count: 0,
+            seen_fault_count: 0,
+            fault_win_open: false,
+            mem_goto_input: String::new(),
+            mem_scroll_to: None,
+            mem_selected: None,
+            mem_edit_input: String::new(),
+        }
+    }
+
+    // Opens, reloads or closes the program, with the same locking order as the CPU thread
+    fn change_program(&mut self, change: impl FnOnce(&mut Loader, &mut Chip8, &mut Debugger) -> Result<()>) {
+        let mut loader = self.loader.lock().expect("Lock poisoned");
+        let mut debugger = self.debugger.lock().expect("Lock poisoned");
+        let mut chip8 = self.chip8.write().expect("Lock poisoned");
+        match change(&mut loader, &mut chip8, &mut debugger) {
+            Ok(()) => {
+                self.load_error = None;
+                self.mem_selected = None;
+            }
+            Err(err) => self.load_error = Some(err.to_string()),
+        }
+    }
+
+    fn open_program(&mut self, path: &str) {
+        self.change_program(|loader, chip8, debugger| {
+            loader
+                .open(path, chip8, debugger)
+                .map_err(|err| err.context(format!("Couldn't open {path}")))
+        });
+    }
+
+    // Lists the subdirectories then the programs of `dir`, hidden entries excluded
+    fn browse(&mut self, dir: PathBuf) {
+        let mut entries: Vec<(PathBuf, bool)> = std::fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                    .map(|entry| (entry.path(), entry.path().is_dir()))
+                    .filter(|(path, is_dir)| {
+                        *is_dir
+                            || self.browser_show_all
+                            || path
+                                .extension()
+                                .is_some_and(|ext| PROGRAM_EXTENSIONS.contains(&&*ext.to_string_lossy().to_lowercase()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        entries.sort_by(|(path_a, is_dir_a), (path_b, is_dir_b)| is_dir_b.cmp(is_dir_a).then(path_a.cmp(path_b)));
+        self.browser_dir = dir;
+        self.browser_entries = entries;
+    }
+
+    fn open_prefs(&mut self) {
+        self.prefs = self.settings.read().expect("Lock poisoned").clone();
+        self.prefs_keys = binding_names(&self.prefs.keys);
+        let loader = self.loader.lock().expect("Lock poisoned");
+        self.prefs_program = loader.get_path().zip(loader.get_program_sha1()).map(|(path, sha1)| {
+            let name = Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+            (name.into_owned(), sha1.to_string())
+        });
+        self.prefs_error = None;
+        self.prefs_win_open = true;
+    }
+
+    fn save_prefs(&mut self) -> Result<()> {
+        for (key, names) in self.prefs_keys.iter().enumerate() {
+            let keycodes = names
+                .split_whitespace()
+                .map(|name| {
+                    key_from_name(name)
+                        .ok_or_else(|| anyhow!(ConfigError::new(format!("unknown key `{name}` bound to {key:X}"))))
+                })
+                .collect::<Result<_>>()?;
+            self.prefs.keys.set(key as u8, keycodes);
         }
+        self.prefs.roms.retain(|_, machine| !machine.is_empty());
+        self.prefs.save()?;
+        *self.settings.write().expect("Lock poisoned") = self.prefs.clone();
+        Ok(())
     }
 
     // Create the UI using egui.
     fn ui(&mut self, ctx: &Context) {
+        let loader = self.loader.lock().expect("Lock poisoned");
+        let locked = loader.is_locked();
+        let loaded = loader.get_path().is_some();
+        let recent = loader.get_recent().to_vec();
+        drop(loader);
+        if let Some(path) = ctx.input(|i| i.raw.dropped_files.iter().find_map(|file| file.path.clone()))
+            && !locked
+        {
+            self.open_program(&path.to_string_lossy());
+        }
+
+        let debugger = self.debugger.lock().expect("Lock poisoned");
+        if debugger.get_hit_count() != self.seen_hit_count {
+            self.seen_hit_count = debugger.get_hit_count();
+            self.debug_win_open = true;
+        }
+        if debugger.get_fault_count() != self.seen_fault_count {
+            self.seen_fault_count = debugger.get_fault_count();
+            match debugger.get_fault() {
+                Some((_, FaultPolicy::Break)) => self.debug_win_open = true,
+                Some(_) => self.fault_win_open = true,
+                None => (),
+            }
+        }
+        drop(debugger);
+
         egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    // Movies are tied to their program
+                    ui.add_enabled_ui(!locked, |ui| {
+                        if ui.button("Open...").clicked() {
+                            self.browse(self.browser_dir.clone());
+                            self.open_win_open = true;
+                            ui.close_menu();
+                        }
+                        ui.add_enabled_ui(!recent.is_empty(), |ui| {
+                            ui.menu_button("Open Recent", |ui| {
+                                for path in &recent {
+                                    if ui.button(path).clicked() {
+                                        self.open_program(path);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                        ui.add_enabled_ui(loaded, |ui| {
+                            if ui.button("Program Info...").clicked() {
+                                self.info_win_open = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Reset").clicked() {
+                                self.change_program(Loader::reset);
+                                ui.close_menu();
+                            }
+                            if ui.button("Close").clicked() {
+                                self.change_program(|loader, chip8, debugger| {
+                                    loader.close(chip8, debugger);
+                                    Ok(())
+                                });
+                                ui.close_menu();
+                            }
+                        });
+                    });
+                    ui.separator();
+                    if ui.button("Preferences...").clicked() {
+                        self.open_prefs();
+                        ui.close_menu();
+                    }
                     if ui.button("About...").clicked() {
                         self.about_win_open = true;
                         ui.close_menu();
                     }
-                })
+                });
+                ui.menu_button("Debug", |ui| {
+                    if ui.button("Debugger...").clicked() {
+                        self.debug_win_open = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Memory...").clicked() {
+                        self.mem_win_open = true;
+                        ui.close_menu();
+                    }
+                });
             });
         });
 
+        if !loaded {
+            egui::Area::new("no_program")
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label("Open a program from the File menu, or drop it on the window");
+                });
+        }
+
+        let mut opened = None;
+        let mut browsed = None;
+        egui::Window::new("Open")
+            .open(&mut self.open_win_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Up").clicked() {
+                        browsed = self.browser_dir.parent().map(Path::to_path_buf);
+                    }
+                    ui.monospace(self.browser_dir.to_string_lossy());
+                });
+                if ui.checkbox(&mut self.browser_show_all, "Show all files").changed() {
+                    browsed = Some(self.browser_dir.clone());
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for (path, is_dir) in &self.browser_entries {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if *is_dir {
+                            if ui.selectable_label(false, format!("{name}/")).clicked() {
+                                browsed = Some(path.clone());
+                            }
+                        } else if ui.selectable_label(false, name).clicked() {
+                            opened = Some(path.clone());
+                        }
+                    }
+                });
+            });
+        if let Some(dir) = browsed {
+            self.browse(dir);
+        }
+        if let Some(path) = opened {
+            self.open_win_open = false;
+            self.open_program(&path.to_string_lossy());
+        }
+
+        egui::Window::new("Program Info")
+            .open(&mut self.info_win_open)
+            .show(ctx, |ui| {
+                let loader = self.loader.lock().expect("Lock poisoned");
+                let Some(info) = loader.get_program_info() else {
+                    ui.label("This program isn't in the CHIP-8 database.");
+                    ui.monospace(format!("SHA-1 {}", loader.get_program_sha1().unwrap_or_default()));
+                    return;
+                };
+                ui.heading(&info.title);
+                if !info.authors.is_empty() {
+                    ui.label(format!("By {}", info.authors.join(", ")));
+                }
+                if let Some(release) = &info.release {
+                    ui.label(format!("Released in {release}"));
+                }
+                if let Some(platform) = &info.platform_name {
+                    if info.platform.is_some() {
+                        ui.label(format!("Made for {platform}"));
+                    } else {
+                        ui.colored_label(egui::Color32::LIGHT_RED, format!("Made for {platform}, which isn't emulated"));
+                    }
+                }
+                if let Some(description) = &info.description {
+                    ui.separator();
+                    ui.label(description);
+                }
+                if !info.keys.is_empty() {
+                    ui.separator();
+                    ui.label("Keys");
+                    egui::Grid::new("info_keys").show(ui, |ui| {
+                        for (action, key) in &info.keys {
+                            ui.label(action);
+                            ui.monospace(format!("{key:X}"));
+                            ui.end_row();
+                        }
+                    });
+                }
+            });
+
+        let mut prefs_saved = false;
+        egui::Window::new("Preferences")
+            .open(&mut self.prefs_win_open)
+            .show(ctx, |ui| {
+                egui::CollapsingHeader::new("Display").default_open(true).show(ui, |ui| {
+                    egui::Grid::new("prefs_display").show(ui, |ui| {
+                        ui.label("Scale (on restart)");
+                        ui.add(egui::DragValue::new(&mut self.prefs.scale).clamp_range(1..=64));
+                        ui.end_row();
+                        ui.label("Background");
+                        ui.color_edit_button_srgb(&mut self.prefs.palette.background);
+                        ui.end_row();
+                        ui.label("Foreground");
+                        ui.color_edit_button_srgb(&mut self.prefs.palette.foreground);
+                        ui.end_row();
+                        ui.label("XO-CHIP plane 2");
+                        ui.color_edit_button_srgb(&mut self.prefs.palette.plane2);
+                        ui.end_row();
+                        ui.label("XO-CHIP both planes");
+                        ui.color_edit_button_srgb(&mut self.prefs.palette.both_planes);
+                        ui.end_row();
+                    });
+                });
+                ui.checkbox(
+                    &mut self.prefs.identify,
+                    "Set programs up as the CHIP-8 database says, colours included",
+                );
+                egui::CollapsingHeader::new("Audio").default_open(true).show(ui, |ui| {
+                    ui.add(egui::Slider::new(&mut self.prefs.volume, 0.0..=1.0).text("Volume"));
+                });
+                egui::CollapsingHeader::new("Emulation").show(ui, |ui| {
+                    ui.label("Applied to the programs opened next, unless given on the command line");
+                    machine_settings_ui(ui, "prefs_machine", &mut self.prefs.machine);
+                    if let Some((name, sha1)) = &self.prefs_program {
+                        ui.separator();
+                        ui.label(format!("Overrides for {name}, applied on reset"));
+                        machine_settings_ui(ui, "prefs_program", self.prefs.roms.entry(sha1.clone()).or_default());
+                    }
+                });
+                egui::CollapsingHeader::new("Keys").show(ui, |ui| {
+                    ui.label("Host keys bound to each key of the keypad, e.g. `KeyX Numpad0`");
+                    egui::Grid::new("prefs_keys").show(ui, |ui| {
+                        for (key, names) in self.prefs_keys.iter_mut().enumerate() {
+                            ui.monospace(format!("{key:X}"));
+                            ui.add(egui::TextEdit::singleline(names).desired_width(150.0).font(egui::TextStyle::Monospace));
+                            if key % 2 == 1 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        prefs_saved = true;
+                    }
+                    if ui.button("Restore Defaults").clicked() {
+                        // Programs keep their own settings
+                        self.prefs = Settings {
+                            roms: std::mem::take(&mut self.prefs.roms),
+                            ..Settings::default()
+                        };
+                        self.prefs_keys = binding_names(&self.prefs.keys);
+                    }
+                });
+                if let Some(err) = &self.prefs_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, err);
+                }
+            });
+        if prefs_saved {
+            match self.save_prefs() {
+                Ok(()) => self.prefs_win_open = false,
+                Err(err) => self.prefs_error = Some(err.to_string()),
+            }
+        }
+
+        let mut load_error_open = self.load_error.is_some();
+        egui::Window::new("Error")
+            .open(&mut load_error_open)
+            .show(ctx, |ui| {
+                ui.colored_label(egui::Color32::LIGHT_RED, self.load_error.as_deref().unwrap_or_default());
+            });
+        if !load_error_open {
+            self.load_error = None;
+        }
+
+        egui::Window::new("Debugger")
+            .open(&mut self.debug_win_open)
+            .show(ctx, |ui| {
+                // Same locking order as the CPU thread
+                let mut debugger = self.debugger.lock().expect("Lock poisoned");
+                let chip8 = self.chip8.read().expect("Lock poisoned");
+
+                ui.horizontal(|ui| {
+                    let halted = debugger.is_halted();
+                    if debugger.is_paused() {
+                        if ui.add_enabled(!halted, egui::Button::new("Continue")).clicked() {
+                            debugger.resume();
+                        }
+                    } else if ui.button("Pause").clicked() {
+                        debugger.pause();
+                    }
+                    ui.add_enabled_ui(debugger.is_paused() && !halted, |ui| {
+                        if ui.button("Step").clicked() {
+                            debugger.step();
+                        }
+                        if ui.button("Step Over").clicked() {
+                            debugger.step_over(&chip8);
+                        }
+                        if ui.button("Step Out").clicked() {
+                            debugger.step_out(&chip8);
+                        }
+                    });
+                    ui.label(match (chip8.is_halted() || halted, debugger.is_paused()) {
+                        (true, _) => "Halted",
+                        (false, true) => "Paused",
+                        (false, false) => "Running",
+                    });
+                });
+                if let Some(hit) = debugger.get_hit() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Breakpoint hit : {hit}"));
+                }
+                if let Some((fault, _)) = debugger.get_fault() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, fault.to_string());
+                }
+
+                ui.separator();
+                ui.label("Breakpoints");
+                let mut removed = None;
+                for (i, breakpoint) in debugger.get_breakpoints().get().iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("x").clicked() {
+                            removed = Some(i);
+                        }
+                        ui.monospace(breakpoint.to_string());
+                    });
+                }
+                if let Some(i) = removed {
+                    debugger.get_breakpoints_mut().remove(i);
+                }
+                ui.horizontal(|ui| {
+                    let input = ui.add(
+                        egui::TextEdit::singleline(&mut self.breakpoint_input)
+                            .hint_text("200, write 300 30f, vA == 10, op DXYN...")
+                            .font(egui::TextStyle::Monospace),
+                    );
+                    let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if ui.button("Add").clicked() || submitted {
+                        match Breakpoint::parse(&self.breakpoint_input) {
+                            Ok(breakpoint) => {
+                                debugger.get_breakpoints_mut().add(breakpoint);
+                                self.breakpoint_input.clear();
+                                self.breakpoint_error = None;
+                            }
+                            Err(err) => self.breakpoint_error = Some(err.to_string()),
+                        }
+                    }
+                });
+                if let Some(err) = &self.breakpoint_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, err);
+                }
+
+                ui.separator();
+                let state = chip8.get_state();
+                egui::Grid::new("debugger_registers").show(ui, |ui| {
+                    for (row, regs) in state.v.chunks(4).enumerate() {
+                        for (col, reg) in regs.iter().enumerate() {
+                            ui.monospace(format!("V{:X} {:02x}", row * 4 + col, reg));
+                        }
+                        ui.end_row();
+                    }
+                    ui.monospace(format!("I  {:03x}", state.i));
+                    ui.monospace(format!("PC {:03x}", state.pc));
+                    ui.monospace(format!("SP {:03x}", state.sp));
+                    ui.end_row();
+                    ui.monospace(format!("DT {:02x}", state.delay));
+                    ui.monospace(format!("ST {:02x}", state.sound));
+                    ui.end_row();
+                });
+
+                ui.separator();
+                ui.label("Call stack");
+                let call_stack = chip8.get_call_stack();
+                if call_stack.is_empty() {
+                    ui.monospace("(main)");
+                }
+                for addr in call_stack.iter().rev() {
+                    ui.monospace(format!("{addr:03x}"));
+                }
+
+                ui.separator();
+                ui.label("Disassembly");
+                let start = state.pc.saturating_sub(DISAS_LINES_BEFORE * 2);
+                for (addr, text) in disas_mem(chip8.get_mem(), start, DISAS_LINES) {
+                    if addr == state.pc {
+                        ui.label(RichText::new(format!("> {addr:03x}: {text}")).monospace().strong());
+                    } else {
+                        ui.monospace(format!("  {addr:03x}: {text}"));
+                    }
+                }
+
+                egui::CollapsingHeader::new("Backtrace").show(ui, |ui| {
+                    for (addr, text) in chip8.get_backtrace().entries() {
+                        ui.monospace(format!("{addr:03x}: {text}"));
+                    }
+                });
+            });
+
+        egui::Window::new("Memory")
+            .open(&mut self.mem_win_open)
+            .show(ctx, |ui| {
+                // Same locking order as the CPU thread
+                let debugger = self.debugger.lock().expect("Lock poisoned");
+                let mut chip8 = self.chip8.write().expect("Lock poisoned");
+                let paused = debugger.is_paused();
+                let platform = chip8.get_platform();
+                let stack_base = platform.stack_base();
+                let region_color = |addr: u16| match addr {
+                    _ if (CHIP8_FONT_START..SCHIP_BIG_FONT_START + 0x10 * SCHIP_BIG_FONT_HEIGHT as u16)
+                        .contains(&addr) =>
+                    {
+                        Some(MEM_COLOR_FONT)
+                    }
+                    _ if (stack_base..stack_base.saturating_add(CHIP8_STACK_LEN)).contains(&addr) => {
+                        Some(MEM_COLOR_STACK)
+                    }
+                    _ if platform == Platform::Chip8
+                        && (CHIP8_DISP_BUF_ADDR..CHIP8_DISP_BUF_ADDR + CHIP8_DISP_BUF_LEN).contains(&addr) =>
+                    {
+                        Some(MEM_COLOR_DISPLAY)
+                    }
+                    _ if (CHIP8_PC_START..stack_base).contains(&addr) => Some(MEM_COLOR_PROGRAM),
+                    _ => None,
+                };
+
+                ui.horizontal(|ui| {
+                    ui.colored_label(MEM_COLOR_FONT, "Font");
+                    ui.colored_label(MEM_COLOR_PROGRAM, "Program");
+                    ui.colored_label(MEM_COLOR_STACK, "Stack");
+                    if platform == Platform::Chip8 {
+                        ui.colored_label(MEM_COLOR_DISPLAY, "Display");
+                    }
+                    ui.label(RichText::new("Written last frame").background_color(MEM_COLOR_WRITTEN));
+                });
+                ui.horizontal(|ui| {
+                    let input = ui.add(
+                        egui::TextEdit::singleline(&mut self.mem_goto_input)
+                            .hint_text("Address")
+                            .desired_width(60.0)
+                            .font(egui::TextStyle::Monospace),
+                    );
+                    let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Go to").clicked() || submitted)
+                        && let Ok(addr) = usize::from_str_radix(self.mem_goto_input.trim_start_matches("0x"), 16)
+                    {
+                        self.mem_scroll_to = Some(addr / MEM_ROW_LEN);
+                    }
+                });
+                match self.mem_selected.filter(|_| paused) {
+                    Some(addr) => {
+                        ui.horizontal(|ui| {
+                            ui.monospace(format!("{addr:03x} ="));
+                            let input = ui.add(
+                                egui::TextEdit::singleline(&mut self.mem_edit_input)
+                                    .desired_width(30.0)
+                                    .font(egui::TextStyle::Monospace),
+                            );
+                            let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            if (ui.button("Set").clicked() || submitted)
+                                && let Ok(value) = u8::from_str_radix(&self.mem_edit_input, 16)
+                            {
+                                let _ = chip8.get_mem_mut().set_byte(addr, value);
+                                self.mem_selected = None;
+                            }
+                        });
+                    }
+                    None => {
+                        ui.label(if paused {
+                            "Click on a byte to edit it"
+                        } else {
+                            "Pause to edit memory"
+                        });
+                    }
+                }
+                ui.separator();
+
+                let mem = chip8.get_mem();
+                let mut written = vec![false; mem.dump().len()];
+                for access in mem.get_last_frame_writes() {
+                    let start = access.addr as usize;
+                    written
+                        .iter_mut()
+                        .skip(start)
+                        .take(access.len as usize)
+                        .for_each(|byte| *byte = true);
+                }
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                let mut scroll = egui::ScrollArea::vertical().auto_shrink([false; 2]);
+                if let Some(row) = self.mem_scroll_to.take() {
+                    scroll = scroll.vertical_scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+                }
+                scroll.show_rows(ui, row_height, mem.dump().len() / MEM_ROW_LEN, |ui, rows| {
+                    for row in rows {
+                        let start = row * MEM_ROW_LEN;
+                        let bytes = &mem.dump()[start..start + MEM_ROW_LEN];
+                        ui.horizontal(|ui| {
+                            ui.spacing_mut().item_spacing.x = 4.0;
+                            ui.monospace(format!("{start:04x}"));
+                            for (i, byte) in bytes.iter().enumerate() {
+                                let addr = (start + i) as u16;
+                                let mut text = RichText::new(format!("{byte:02x}")).monospace();
+                                if let Some(color) = region_color(addr) {
+                                    text = text.color(color);
+                                }
+                                if written[addr as usize] {
+                                    text = text.background_color(MEM_COLOR_WRITTEN);
+                                }
+                                if self.mem_selected == Some(addr) {
+                                    text = text.underline();
+                                }
+                                let label = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                                if label.clicked() && paused {
+                                    self.mem_selected = Some(addr);
+                                    self.mem_edit_input = format!("{byte:02x}");
+                                }
+                            }
+                            let ascii: String = bytes
+                                .iter()
+                                .map(|&byte| if (0x20..0x7F).contains(&byte) { byte as char } else { '.' })
+                                .collect();
+                            ui.monospace(ascii);
+                        });
+                    }
+                });
+            });
+
+        egui::Window::new("Fault")
+            .open(&mut self.fault_win_open)
+            .show(ctx, |ui| {
+                let debugger = self.debugger.lock().expect("Lock poisoned");
+                let chip8 = self.chip8.read().expect("Lock poisoned");
+                match debugger.get_fault() {
+                    Some((fault, _)) => {
+                        ui.colored_label(egui::Color32::LIGHT_RED, fault.to_string());
+                        ui.label("The program was halted. Reset it from the File menu to start it over.");
+                    }
+                    None => {
+                        ui.label("The program was restarted.");
+                    }
+                }
+                ui.separator();
+                ui.label("Backtrace");
+                for (addr, text) in chip8.get_backtrace().entries() {
+                    ui.monospace(format!("{addr:03x}: {text}"));
+                }
+            });
+
         egui::Window::new("About Rusty Chip8")
             .open(&mut self.about_win_open)
             .show(ctx, |ui| {
@@ -185,3 +881,68 @@ impl Gui {
             });
     }
 }
+
+// Host keys bound to each hex key, space separated
+fn binding_names(keymap: &KeyMap) -> [String; 0x10] {
+    std::array::from_fn(|key| {
+        let names: Vec<String> = keymap.get(key as u8).iter().map(|keycode| key_name(*keycode)).collect();
+        names.join(" ")
+    })
+}
+
+// Edits the settings a program runs with, unset ones being inherited
+fn machine_settings_ui(ui: &mut egui::Ui, id: &str, machine: &mut MachineSettings) {
+    egui::Grid::new(id).show(ui, |ui| {
+        ui.label("Platform");
+        let name = |platform: Option<Platform>| platform.map_or("Default".to_string(), |platform| format!("{platform:?}"));
+        egui::ComboBox::from_id_source(format!("{id}_platform"))
+            .selected_text(name(machine.platform))
+            .show_ui(ui, |ui| {
+                for platform in [None, Some(Platform::Chip8), Some(Platform::SChip), Some(Platform::XoChip)] {
+                    ui.selectable_value(&mut machine.platform, platform, name(platform));
+                }
+            });
+        ui.end_row();
+
+        ui.label("Instructions per frame");
+        ui.horizontal(|ui| {
+            let mut custom = machine.ipf.is_some();
+            if ui.checkbox(&mut custom, "").changed() {
+                machine.ipf = custom.then(|| machine.get_cycles_per_frame());
+            }
+            match &mut machine.ipf {
+                Some(ipf) => ui.add(egui::DragValue::new(ipf).clamp_range(1..=10000)),
+                None => ui.label(format!("{} (platform default)", machine.get_cycles_per_frame())),
+            };
+        });
+        ui.end_row();
+
+        ui.label("Quirks");
+        let mut custom = machine.quirks.is_some();
+        if ui.checkbox(&mut custom, "Custom").changed() {
+            machine.quirks = custom.then(|| machine.get_quirks());
+        }
+        ui.end_row();
+    });
+    if let Some(quirks) = &mut machine.quirks {
+        quirks_ui(ui, id, quirks);
+    }
+}
+
+fn quirks_ui(ui: &mut egui::Ui, id: &str, quirks: &mut Quirks) {
+    ui.checkbox(&mut quirks.shift, "8XY6/8XYE shift VX in place");
+    ui.checkbox(&mut quirks.vf_reset, "8XY1/8XY2/8XY3 reset VF");
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(format!("{id}_memory_increment"))
+            .selected_text(quirks.memory_increment.name())
+            .show_ui(ui, |ui| {
+                for increment in MemoryIncrement::ALL {
+                    ui.selectable_value(&mut quirks.memory_increment, increment, increment.name());
+                }
+            });
+        ui.label("after FX55/FX65");
+    });
+    ui.checkbox(&mut quirks.jump_vx, "BNNN jumps to XNN + VX");
+    ui.checkbox(&mut quirks.display_wait, "DXYN waits for vblank");
+    ui.checkbox(&mut quirks.clipping, "Sprites are clipped");
+}
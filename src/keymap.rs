@@ -0,0 +1,219 @@
+use {
+    anyhow::{Result, anyhow},
+    rusty_chip8::{errors::ConfigError, keypad::Keypad},
+    serde::{Deserialize, Deserializer, Serialize, Serializer, de},
+    std::collections::BTreeMap,
+    winit::keyboard::KeyCode,
+    winit_input_helper::WinitInputHelper,
+};
+
+// Host keys that can be bound to the keypad, named in config files after their winit name.
+// Function keys and Backspace are left out, being used by save slots and rewind.
+const BINDABLE_KEYS: [KeyCode; 70] = [
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::NumpadAdd,
+    KeyCode::NumpadSubtract,
+    KeyCode::NumpadMultiply,
+    KeyCode::NumpadDivide,
+    KeyCode::NumpadDecimal,
+    KeyCode::NumpadComma,
+    KeyCode::NumpadEnter,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::Space,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::Semicolon,
+    KeyCode::Quote,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Backslash,
+];
+
+pub fn key_name(keycode: KeyCode) -> String {
+    format!("{keycode:?}")
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|keycode| key_name(*keycode).eq_ignore_ascii_case(name))
+}
+
+// Host keys bound to each of the 16 keys of the hex keypad
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: [Vec<KeyCode>; 0x10],
+}
+
+impl Default for KeyMap {
+    // The conventional QWERTY layout, mirroring the shape of the COSMAC VIP keypad :
+    //   1 2 3 C      1 2 3 4
+    //   4 5 6 D  ->  Q W E R
+    //   7 8 9 E      A S D F
+    //   A 0 B F      Z X C V
+    fn default() -> Self {
+        Self {
+            bindings: [
+                KeyCode::KeyX,
+                KeyCode::Digit1,
+                KeyCode::Digit2,
+                KeyCode::Digit3,
+                KeyCode::KeyQ,
+                KeyCode::KeyW,
+                KeyCode::KeyE,
+                KeyCode::KeyA,
+                KeyCode::KeyS,
+                KeyCode::KeyD,
+                KeyCode::KeyZ,
+                KeyCode::KeyC,
+                KeyCode::Digit4,
+                KeyCode::KeyR,
+                KeyCode::KeyF,
+                KeyCode::KeyV,
+            ]
+            .map(|keycode| vec![keycode]),
+        }
+    }
+}
+
+impl KeyMap {
+    // Parses lines such as `A = KeyZ Numpad0`, binding the host keys listed to the given hex key,
+    // keys not mentioned keeping their default binding. `#` starts a comment.
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut keymap = Self::default();
+        for (line_nb, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |reason: String| {
+                anyhow!(ConfigError::new(format!(
+                    "line {} : {}",
+                    line_nb + 1,
+                    reason
+                )))
+            };
+            let Some((key, keycodes)) = line.split_once('=') else {
+                return Err(err(format!(
+                    "expected `<hex key> = <host keys>`, got `{line}`"
+                )));
+            };
+            let key = match u8::from_str_radix(key.trim(), 16) {
+                Ok(key) if key < 0x10 => key,
+                _ => return Err(err(format!("`{}` is not a keypad key", key.trim()))),
+            };
+            keymap.bindings[key as usize] = keycodes
+                .split_whitespace()
+                .map(|name| key_from_name(name).ok_or_else(|| err(format!("unknown key `{name}`"))))
+                .collect::<Result<_>>()?;
+        }
+        Ok(keymap)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn get(&self, key: u8) -> &[KeyCode] {
+        &self.bindings[key as usize & 0xF]
+    }
+
+    pub fn set(&mut self, key: u8, keycodes: Vec<KeyCode>) {
+        self.bindings[key as usize & 0xF] = keycodes;
+    }
+
+    pub fn read_keypad(&self, input: &WinitInputHelper) -> Keypad {
+        let mut keypad = Keypad::default();
+        for (key, keycodes) in self.bindings.iter().enumerate() {
+            keypad.set(
+                key as u8,
+                keycodes.iter().any(|keycode| input.key_held(*keycode)),
+            );
+        }
+        keypad
+    }
+}
+
+// Stored in the settings file as a table of hex keys, e.g. `A = ["KeyZ", "Numpad0"]`, keys not
+// mentioned keeping their default binding
+impl Serialize for KeyMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.bindings.iter().enumerate().map(|(key, keycodes)| {
+            let names: Vec<String> = keycodes.iter().map(|keycode| key_name(*keycode)).collect();
+            (format!("{key:X}"), names)
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut keymap = Self::default();
+        for (key, names) in BTreeMap::<String, Vec<String>>::deserialize(deserializer)? {
+            let key = match u8::from_str_radix(&key, 16) {
+                Ok(key) if key < 0x10 => key,
+                _ => return Err(de::Error::custom(format!("`{key}` is not a keypad key"))),
+            };
+            keymap.bindings[key as usize] = names
+                .iter()
+                .map(|name| {
+                    key_from_name(name).ok_or_else(|| de::Error::custom(format!("unknown key `{name}`")))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(keymap)
+    }
+}
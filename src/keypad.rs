@@ -0,0 +1,22 @@
+// State of the 16 keys of the CHIP-8 hex keypad, bit N being set when key N is held
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keypad(pub u16);
+
+impl Keypad {
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.0 & (1 << (key & 0x0F)) != 0
+    }
+
+    pub fn set(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.0 |= 1 << (key & 0x0F);
+        } else {
+            self.0 &= !(1 << (key & 0x0F));
+        }
+    }
+
+    // Lowest key held, if any
+    pub fn first_pressed(&self) -> Option<u8> {
+        (self.0 != 0).then(|| self.0.trailing_zeros() as u8)
+    }
+}
@@ -0,0 +1,17 @@
+pub mod asm;
+pub mod breakpoints;
+pub mod database;
+pub mod debug;
+pub mod debugger;
+pub mod disas;
+pub mod errors;
+pub mod gdbstub;
+pub mod keypad;
+pub mod mem;
+pub mod movie;
+pub mod octo;
+pub mod rewind;
+pub mod savestate;
+pub mod systems;
+pub mod testrom;
+pub mod trace;
@@ -0,0 +1,292 @@
+use {
+    anyhow::Result,
+    log::{error, info},
+    rusty_chip8::{
+        database::{Database, ProgramInfo},
+        debugger::Debugger,
+        movie::MovieHeader,
+        octo::compile,
+        systems::{Chip8, Palette, Platform, System},
+    },
+    sha1_smol::Sha1,
+    std::{
+        path::PathBuf,
+        sync::{Arc, RwLock},
+    },
+};
+
+use crate::settings::{MachineSettings, Settings};
+
+const RECENT_FILES_LEN: usize = 10;
+
+// Octo sources are compiled on the fly, anything else is expected to be a ROM
+pub fn open_program(path: &str, platform: Platform) -> Result<Vec<u8>> {
+    compile_program(path, std::fs::read(path)?, platform)
+}
+
+fn compile_program(path: &str, file_data: Vec<u8>, platform: Platform) -> Result<Vec<u8>> {
+    if path.ends_with(".8o") {
+        compile(&String::from_utf8(file_data)?, platform)
+    } else {
+        Ok(file_data)
+    }
+}
+
+// Directory the emulator keeps its files in, following the XDG convention
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("rusty-chip8"))
+}
+
+// Copy of the CHIP-8 database taking precedence over the bundled one, e.g. a newer one
+fn load_database() -> Database {
+    let Some(dir) = config_dir().map(|dir| dir.join("chip-8-database")) else {
+        return Database::bundled();
+    };
+    let read = |file: &str| std::fs::read_to_string(dir.join(file));
+    let database = match (read("sha1-hashes.json"), read("programs.json"), read("platforms.json")) {
+        (Ok(sha1_hashes), Ok(programs), Ok(platforms)) => Database::parse(&sha1_hashes, &programs, &platforms),
+        _ => return Database::bundled(),
+    };
+    match database {
+        Ok(database) => {
+            info!("Loaded {} programs from the database in {}", database.len(), dir.display());
+            database
+        }
+        Err(err) => {
+            error!("Couldn't load the database in {} : {}", dir.display(), err);
+            Database::bundled()
+        }
+    }
+}
+
+// Programs last opened, most recent first, stored one path per line
+pub struct RecentFiles {
+    paths: Vec<String>,
+    file: Option<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let file = config_dir().map(|dir| dir.join("recent"));
+        let paths = file
+            .as_ref()
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self { paths, file }
+    }
+
+    pub fn get(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn add(&mut self, path: &str) {
+        // Stored absolute, for the list to be usable from any working directory
+        let path = std::fs::canonicalize(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        self.paths.retain(|recent| *recent != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(RECENT_FILES_LEN);
+        if let Err(err) = self.save() {
+            error!("Couldn't save the recent files : {}", err);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(std::fs::write(file, self.paths.join("\n"))?)
+    }
+}
+
+// Program running in the window, which the File menu can replace without restarting the emulator
+pub struct Loader {
+    settings: Arc<RwLock<Settings>>,
+    // Given on the command line, taking precedence over the settings
+    overrides: MachineSettings,
+    seed: Option<u64>,
+    path: Option<String>,
+    // SHA-1 of the program file, which its settings are keyed by
+    program_sha1: Option<String>,
+    program_data: Vec<u8>,
+    program_info: Option<ProgramInfo>,
+    database: Database,
+    cycles_per_frame: u32,
+    // Movies being tied to their program, it can't be changed while recording or playing one
+    locked: bool,
+    // Bumped on each change of program, for the state tied to the previous one to be dropped
+    generation: u64,
+    recent: RecentFiles,
+}
+
+impl Loader {
+    pub fn new(
+        settings: Arc<RwLock<Settings>>,
+        overrides: MachineSettings,
+        seed: Option<u64>,
+    ) -> Self {
+        let cycles_per_frame = overrides
+            .or(settings.read().expect("Lock poisoned").machine)
+            .get_cycles_per_frame();
+        Self {
+            settings,
+            overrides,
+            seed,
+            path: None,
+            program_sha1: None,
+            program_data: Vec::new(),
+            program_info: None,
+            database: load_database(),
+            cycles_per_frame,
+            locked: false,
+            generation: 0,
+            recent: RecentFiles::load(),
+        }
+    }
+
+    pub fn get_settings(&self) -> Arc<RwLock<Settings>> {
+        self.settings.clone()
+    }
+
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn get_program_sha1(&self) -> Option<&str> {
+        self.program_sha1.as_deref()
+    }
+
+    // The program as loaded in memory, Octo sources being compiled
+    pub fn get_program_data(&self) -> &[u8] {
+        &self.program_data
+    }
+
+    // What the database knows of the program, if it's in there
+    pub fn get_program_info(&self) -> Option<&ProgramInfo> {
+        self.program_info.as_ref()
+    }
+
+    // Colours the program is drawn with, when the database gives some
+    pub fn get_palette(&self) -> Option<Palette> {
+        let identify = self.settings.read().expect("Lock poisoned").identify;
+        self.program_info.as_ref().and_then(|info| info.palette).filter(|_| identify)
+    }
+
+    pub fn get_cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get_recent(&self) -> &[String] {
+        self.recent.get()
+    }
+
+    // Runs the program from the start on a new machine, set up from the command line and the
+    // settings, the debugger keeping its breakpoints
+    pub fn open(&mut self, path: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> Result<()> {
+        let file_data = std::fs::read(path)?;
+        let program_sha1 = Sha1::from(&file_data).digest().to_string();
+        let program_info = self.database.lookup(&program_sha1);
+        let identified = program_info.as_ref().map_or_else(MachineSettings::default, |info| MachineSettings {
+            platform: info.platform,
+            quirks: info.quirks,
+            ipf: info.cycles_per_frame,
+        });
+        let machine = self
+            .settings
+            .read()
+            .expect("Lock poisoned")
+            .machine_for(&program_sha1, self.overrides, identified);
+        let program_data = compile_program(path, file_data, machine.get_platform())?;
+        let mut new_chip8 = Chip8::init(machine.get_platform(), machine.get_quirks(), self.seed);
+        new_chip8.load_program(&program_data)?;
+        self.cycles_per_frame = machine.get_cycles_per_frame();
+        self.program_info = program_info;
+        self.start(path, program_sha1, program_data, new_chip8, chip8, debugger);
+        Ok(())
+    }
+
+    // Runs the program of a movie on the machine it was recorded with, until the emulator exits
+    pub fn open_movie(
+        &mut self,
+        path: &str,
+        header: &MovieHeader,
+        chip8: &mut Chip8,
+        debugger: &mut Debugger,
+    ) -> Result<()> {
+        let file_data = std::fs::read(path)?;
+        let program_sha1 = Sha1::from(&file_data).digest().to_string();
+        let program_data = compile_program(path, file_data, header.platform)?;
+        header.check_program(&program_data)?;
+        let mut new_chip8 = header.init_system();
+        new_chip8.load_program(&program_data)?;
+        self.cycles_per_frame = header.cycles_per_frame;
+        self.program_info = self.database.lookup(&program_sha1);
+        self.start(path, program_sha1, program_data, new_chip8, chip8, debugger);
+        self.locked = true;
+        Ok(())
+    }
+
+    fn start(
+        &mut self,
+        path: &str,
+        program_sha1: String,
+        program_data: Vec<u8>,
+        new_chip8: Chip8,
+        chip8: &mut Chip8,
+        debugger: &mut Debugger,
+    ) {
+        *chip8 = new_chip8;
+        debugger.set_reset_state(chip8.save_state());
+        debugger.clear_halt();
+        debugger.resume();
+        self.path = Some(path.to_string());
+        self.program_sha1 = Some(program_sha1);
+        self.program_data = program_data;
+        self.generation += 1;
+        self.recent.add(path);
+    }
+
+    // Reloads the program from its file, picking up any change made to it or to its settings
+    pub fn reset(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) -> Result<()> {
+        match self.path.clone() {
+            Some(path) => self.open(&path, chip8, debugger),
+            None => Ok(()),
+        }
+    }
+
+    pub fn close(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) {
+        let machine = self
+            .overrides
+            .or(self.settings.read().expect("Lock poisoned").machine);
+        *chip8 = Chip8::init(machine.get_platform(), machine.get_quirks(), self.seed);
+        debugger.set_reset_state(chip8.save_state());
+        debugger.clear_halt();
+        debugger.pause();
+        self.path = None;
+        self.program_sha1 = None;
+        self.program_data.clear();
+        self.program_info = None;
+        self.generation += 1;
+    }
+}
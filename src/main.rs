@@ -1,12 +1,15 @@
 use {
     anyhow::Result,
-    log::error,
+    log::{error, info},
     pixels::{Pixels, SurfaceTexture},
     rodio::{OutputStream, Sink},
     std::{
-        sync::{Arc, RwLock},
-        time::Duration,
+        fs::File,
+        io::BufWriter,
+        path::Path,
         process::exit,
+        sync::{Arc, Mutex, RwLock},
+        time::{Duration, Instant},
     },
     winit::{
         dpi::LogicalSize,
@@ -18,42 +21,193 @@ use {
     winit_input_helper::WinitInputHelper,
 };
 
-mod errors;
+mod audio;
+mod cli;
 mod gui;
-mod mem;
-mod systems;
-mod debug;
-mod disas;
+mod keymap;
+mod loader;
+mod repl;
+mod settings;
 use crate::{
+    audio::Beeper,
+    cli::Args,
     gui::Framework,
-    systems::{Chip8, System, CHIP8_DISP_HEIGHT, CHIP8_DISP_WIDTH},
+    keymap::KeyMap,
+    loader::{Loader, open_program},
+    repl::Repl,
+    settings::{MachineSettings, Settings},
 };
+use rusty_chip8::{
+    debugger::Debugger,
+    gdbstub::GdbStub,
+    asm::assemble_file,
+    disas::disassemble_rom,
+    mem::Memory16Bit,
+    movie::{Movie, MovieHeader, MoviePlayer, MovieRecorder},
+    rewind::Rewind,
+    systems::{BuzzerState, Chip8, System, CHIP8_DISP_HEIGHT, CHIP8_DISP_WIDTH},
+    testrom::{Framebuffer, run_headless},
+    trace::{Trace, Tracer},
+};
+
+const FRAME_DURATION: Duration = Duration::from_nanos(16_666_667); // 60Hz
+// Held to play the emulation backwards
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+
+// F1-F9 load the matching save slot, or save to it with shift held
+const SAVE_SLOT_KEYS: [KeyCode; 9] = [
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+];
+
+fn save_slot_path(program_path: &str, slot: usize) -> String {
+    format!("{program_path}.state{slot}")
+}
+
+fn save_to_slot(chip8: &Chip8, program_path: &str, slot: usize) -> Result<()> {
+    Ok(std::fs::write(save_slot_path(program_path, slot), chip8.save_state())?)
+}
 
-const SCALE: u32 = 16;
-const WIN_WIDTH: u32 = CHIP8_DISP_WIDTH as u32 * SCALE;
-const WIN_HEIGHT: u32 = CHIP8_DISP_HEIGHT as u32 * SCALE; // TODO: add egui toolbar height ?
+fn load_from_slot(chip8: &mut Chip8, program_path: &str, slot: usize) -> Result<()> {
+    chip8.load_state(&std::fs::read(save_slot_path(program_path, slot))?)
+}
 
-fn open_bytes(path: &String) -> Result<Vec<u8>> {
-    Ok(std::fs::read(path)?)
+// Only the command line sets the machine up, the settings file being ignored for runs to be
+// reproducible anywhere
+fn run_test_rom(args: &Args, machine: MachineSettings) -> Result<()> {
+    let platform = machine.get_platform();
+    let mut chip8 = Chip8::init(platform, machine.get_quirks(), Some(args.seed.unwrap_or_default()));
+    chip8.load_program(&open_program(args.path.as_deref().unwrap_or_default(), platform)?)?;
+    for (addr, value) in &args.pokes {
+        chip8.get_mem_mut().set_byte(*addr, *value)?;
+    }
+    run_headless(&mut chip8, args.test_frames, machine.get_cycles_per_frame())?;
+    let framebuffer = Framebuffer::capture(&chip8);
+    if let Some(dump_path) = &args.dump {
+        std::fs::write(dump_path, framebuffer.to_pbm())?;
+    }
+    match &args.expect {
+        Some(expect_path) => {
+            let diff = framebuffer.diff(&Framebuffer::parse_pbm(&std::fs::read(expect_path)?)?);
+            if diff != 0 {
+                print!("{framebuffer}");
+                println!("{diff} pixels differ from {expect_path}");
+                exit(1);
+            }
+            println!("The display matches {expect_path}");
+        }
+        None if args.dump.is_none() => print!("{framebuffer}"),
+        None => (),
+    }
+    Ok(())
+}
+
+// Title and authors of the program when the database knows it, its file name otherwise
+fn window_title(loader: &Loader) -> String {
+    let name = match (loader.get_program_info(), loader.get_path()) {
+        (Some(info), _) if info.authors.is_empty() => info.title.clone(),
+        (Some(info), _) => format!("{} by {}", info.title, info.authors.join(", ")),
+        (None, Some(path)) => Path::new(path).file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        (None, None) => return "Rusty Chip8".to_string(),
+    };
+    format!("Rusty Chip8 - {name}")
 }
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    // rather use GUI techniques
-    let args: Vec<String> = std::env::args().collect();
-    let path = if let Some(arg) = args.get(1) {
-        arg
-    } else {
-        println!("Usage : emu [CHIP-8 program]");
-        exit(1);
+    let args = Args::parse();
+    // Checked by `Args::parse` to be given along with these options
+    let program_path = || args.path.as_deref().unwrap_or_default();
+    if let Some(trace_path) = &args.print_trace {
+        for record in Trace::parse(&std::fs::read(trace_path)?)?.records {
+            println!("{record}");
+        }
+        return Ok(());
+    }
+    let overrides = MachineSettings {
+        platform: args.platform,
+        quirks: args.quirks,
+        ipf: args.cycles_per_frame,
     };
+    if args.test_rom {
+        return run_test_rom(&args, overrides);
+    }
+    let settings = Settings::load()?;
+    let platform = overrides.or(settings.machine).get_platform();
+    if args.disassemble {
+        print!("{}", disassemble_rom(&open_program(program_path(), platform)?)?);
+        return Ok(());
+    }
+    if let Some(rom_path) = &args.assemble {
+        let rom = if program_path().ends_with(".8o") {
+            open_program(program_path(), platform)?
+        } else {
+            assemble_file(program_path())?
+        };
+        std::fs::write(rom_path, rom)?;
+        return Ok(());
+    }
+
+    let scale = settings.scale.max(1);
+    let settings = Arc::new(RwLock::new(settings));
+    let mut loader = Loader::new(settings.clone(), overrides, args.seed);
+    // Replaced by the machine the program runs on
+    let mut chip8 = Chip8::init(platform, platform.default_quirks(), args.seed);
+    let mut debugger = Debugger::new();
+    debugger.set_fault_policies(args.fault_policies);
+    let movie = match &args.play {
+        Some(movie_path) => Some(Movie::parse(&std::fs::read(movie_path)?)?),
+        None => None,
+    };
+    match (&args.path, &movie) {
+        (Some(path), Some(movie)) => loader.open_movie(path, &movie.header, &mut chip8, &mut debugger)?,
+        (Some(path), None) => loader.open(path, &mut chip8, &mut debugger)?,
+        // Waits for a program to be opened from the File menu
+        (None, _) => loader.close(&mut chip8, &mut debugger),
+    }
+    info!("RNG seed : {}", chip8.get_seed());
+    if let Some(trace_path) = &args.trace {
+        let mut tracer = Tracer::new(BufWriter::new(File::create(trace_path)?), args.trace_format)?;
+        if let Some(addrs) = args.trace_addrs.clone() {
+            tracer.set_addr_filter(addrs);
+        }
+        if let Some(frames) = args.trace_frames.clone() {
+            tracer.set_frame_filter(frames);
+        }
+        debugger.set_tracer(tracer);
+    }
+    if args.debug {
+        return Repl::new(chip8, debugger, loader.get_cycles_per_frame()).run();
+    }
+    let mut recorder = match &args.record {
+        Some(movie_path) => {
+            loader.lock();
+            Some(MovieRecorder::new(
+                File::create(movie_path)?,
+                &MovieHeader::new(loader.get_program_data(), &chip8, loader.get_cycles_per_frame()),
+            )?)
+        }
+        None => None,
+    };
+    let mut movie_player = movie.map(MoviePlayer::new);
 
     let event_loop = EventLoop::new()?;
     let input = Arc::new(RwLock::new(WinitInputHelper::new()));
     let input_shared = input.clone();
     let window = {
-        let size = LogicalSize::new(WIN_WIDTH as f64, WIN_HEIGHT as f64);
+        // TODO: add egui toolbar height ?
+        let size = LogicalSize::new(
+            (CHIP8_DISP_WIDTH as u32 * scale) as f64,
+            (CHIP8_DISP_HEIGHT as u32 * scale) as f64,
+        );
         WindowBuilder::new()
             .with_title("Rusty Chip8")
             .with_inner_size(size)
@@ -61,6 +215,23 @@ fn main() -> Result<()> {
             .build(&event_loop)?
     };
 
+    let chip8 = Arc::new(RwLock::new(chip8));
+    let chip8_share = chip8.clone();
+    let debugger = Arc::new(Mutex::new(debugger));
+    let debugger_share = debugger.clone();
+    let loader = Arc::new(Mutex::new(loader));
+    let loader_share = loader.clone();
+    if let Some(port) = args.gdb_port {
+        // Waits for gdb to continue
+        debugger.lock().expect("Lock poisoned").pause();
+        let stub = GdbStub::new(chip8.clone(), debugger.clone());
+        std::thread::spawn(move || {
+            if let Err(err) = stub.listen(("127.0.0.1", port)) {
+                error!("gdb stub stopped : {}", err);
+            }
+        });
+    }
+
     let (mut pixels, mut framework) = {
         let window_size = window.inner_size();
         let scale_factor = window.scale_factor() as f32;
@@ -69,37 +240,131 @@ fn main() -> Result<()> {
         let pixels = Pixels::new(CHIP8_DISP_WIDTH as u32, CHIP8_DISP_HEIGHT as u32, surface_texture)?;
         let framework = Framework::new(
             &event_loop,
-            window_size.width,
-            window_size.height,
+            window_size,
             scale_factor,
             &pixels,
+            chip8.clone(),
+            debugger.clone(),
+            loader.clone(),
         );
 
         (pixels, framework)
     };
 
-    let program_data = open_bytes(path)?;
-    let chip8 = Arc::new(RwLock::new(Chip8::init()));
-    chip8.write().expect("Lock poisoned").load_program(&program_data)?;
-    let chip8_share = chip8.clone();
+    let rewind_secs = args.rewind_secs;
+    // Taking precedence over the keys of the settings
+    let keymap = match &args.keymap {
+        Some(keymap_path) => Some(KeyMap::load(keymap_path)?),
+        None => None,
+    };
+    let settings_share = settings.clone();
 
     let chip8_thread = std::thread::spawn(move || {
         let stream_handle = OutputStream::try_default().expect("No sound output available. This SHOULDN'T panic but a weird (rodio ?) bug forces me to do this.");
-        let sink = Sink::try_new(&stream_handle.1).ok();
+        let mut beeper = Sink::try_new(&stream_handle.1).ok().map(Beeper::new);
+        let mut rewind = Rewind::new(rewind_secs * 60);
+        let mut next_frame = Instant::now();
+        let mut generation = 0;
         loop {
+            // Snapshots of another program can't be rewound to
+            let loader = loader_share.lock().expect("Lock poisoned");
+            let (loaded_generation, cycles_per_frame) = (loader.get_generation(), loader.get_cycles_per_frame());
+            drop(loader);
+            if loaded_generation != generation {
+                generation = loaded_generation;
+                rewind.clear();
+            }
+            let mut debugger = debugger_share.lock().expect("Lock poisoned");
+            // Nothing runs while paused, movies and rewind included
+            let paused = debugger.is_paused();
+            let input = input_shared.read().expect("Lock poisoned");
+            let settings = settings_share.read().expect("Lock poisoned");
+            let volume = settings.volume;
+            // Movies take over the keypad until their end, and can't be rewound
+            let keypad = match movie_player.as_mut() {
+                Some(player) if !paused => player.keypad(),
+                _ => None,
+            }
+            .unwrap_or_else(|| keymap.as_ref().unwrap_or(&settings.keys).read_keypad(&input));
+            drop(settings);
+            let rewinding = input.key_held(REWIND_KEY)
+                && !paused
+                && recorder.is_none()
+                && movie_player.is_none();
+            drop(input);
+            let keypad = match recorder.as_mut() {
+                Some(recorder) if !paused => recorder.keypad(keypad),
+                _ => keypad,
+            };
             let mut chip8 = chip8_share.write().expect("Lock poisoned");
-            if let Err(e) = chip8.exec_instruction(input_shared.clone(), sink.as_ref()) {
+            chip8.set_keypad(keypad);
+            let res = if rewinding {
+                match rewind.pop() {
+                    Some(state) => chip8.load_state(&state),
+                    None => Ok(()),
+                }
+            } else {
+                let hit_count = debugger.get_hit_count();
+                let fault_count = debugger.get_fault_count();
+                let res = debugger.run_frame(&mut chip8, cycles_per_frame);
+                // Movies only move on to the next frame once the current one ended, not when the
+                // debugger paused within it
+                if let Ok(true) = res {
+                    if let Some(player) = &mut movie_player {
+                        player.end_frame();
+                    }
+                    if let Some(Err(err)) = recorder.as_mut().map(MovieRecorder::end_frame) {
+                        error!("Stopped recording the movie: {}", err);
+                        recorder = None;
+                    }
+                }
+                if let Some(hit) = debugger.get_hit().filter(|_| debugger.get_hit_count() != hit_count) {
+                    info!("Breakpoint hit : {}\n{}\n{}", hit, chip8.get_state(), chip8.get_backtrace());
+                }
+                if let Some((fault, _)) = debugger.get_fault().filter(|_| debugger.get_fault_count() != fault_count) {
+                    error!("{}\n{}\n{}", fault, chip8.get_state(), chip8.get_backtrace());
+                }
+                if !paused {
+                    rewind.push(chip8.save_state());
+                }
+                res.map(|_| ())
+            };
+            // Keeps the window usable, with the CPU stopped
+            if res.is_err() {
+                debugger.pause();
+            }
+            drop(debugger);
+            if let Some(beeper) = &mut beeper {
+                beeper.set_volume(volume);
+                beeper.update(if paused {
+                    BuzzerState::Off
+                } else {
+                    chip8.get_buzzer_state()
+                });
+            }
+            if let Err(e) = res {
                 println!("{e}");
+                println!("RNG seed : {}", chip8.get_seed());
                 println!("{}", chip8.get_state());
                 println!("{}", chip8.get_mem());
                 println!("{}", chip8.get_backtrace());
-                return;
             }
             drop(chip8);
-            std::thread::sleep(Duration::from_micros(200));
+
+            next_frame += FRAME_DURATION;
+            let now = Instant::now();
+            if next_frame > now {
+                std::thread::sleep(next_frame - now);
+            } else if now - next_frame > FRAME_DURATION {
+                // Don't try to catch up if we lagged behind by more than a frame
+                next_frame = now;
+            }
         }
     });
 
+    let mut disp_size = (CHIP8_DISP_WIDTH, CHIP8_DISP_HEIGHT);
+    // Set to the program's name on each change of program
+    let mut title_generation = None;
     let res =
         event_loop.run(|event, elwt| {
             // Handle input events
@@ -113,6 +378,39 @@ fn main() -> Result<()> {
                     return;
                 }
 
+                // Save states
+                let loader_guard = loader.lock().expect("Lock poisoned");
+                let path = loader_guard.get_path().map(str::to_string);
+                let generation = loader_guard.get_generation();
+                // Like rewinding, loading a state would change the machine outside of the movie
+                let movie_running = loader_guard.is_locked();
+                if title_generation != Some(generation) {
+                    title_generation = Some(generation);
+                    window.set_title(&window_title(&loader_guard));
+                }
+                drop(loader_guard);
+                for (i, key) in SAVE_SLOT_KEYS.iter().enumerate() {
+                    let input = input.read().expect("Lock poisoned");
+                    let Some(path) = path.as_deref().filter(|_| input.key_pressed(*key)) else {
+                        continue;
+                    };
+                    let slot = i + 1;
+                    let mut chip8 = chip8.write().expect("Lock poisoned");
+                    if input.held_shift() {
+                        match save_to_slot(&chip8, path, slot) {
+                            Ok(()) => info!("Saved state to slot {}", slot),
+                            Err(err) => error!("Couldn't save state to slot {}: {}", slot, err),
+                        }
+                    } else if movie_running {
+                        info!("States can't be loaded while a movie is recorded or played");
+                    } else {
+                        match load_from_slot(&mut chip8, path, slot) {
+                            Ok(()) => info!("Loaded state from slot {}", slot),
+                            Err(err) => error!("Couldn't load state from slot {}: {}", slot, err),
+                        }
+                    }
+                }
+
                 // Update the scale factor
                 // TODO: see how to not crash from scaling with egui ^^
                 if let Some(scale_factor) = input.read().expect("Lock poisoned").scale_factor() {
@@ -130,7 +428,6 @@ fn main() -> Result<()> {
                 }
 
                 // Update internal state and request a redraw
-                // TODO: message cpu thread that this is a vblank ?
                 if chip8_thread.is_finished() {
                     // TODO: gui things
                 }
@@ -141,10 +438,22 @@ fn main() -> Result<()> {
                 // Draw the current frame
                 Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
                     // Draw the world
-                    chip8
-                        .read()
+                    let palette = loader
+                        .lock()
                         .expect("Lock poisoned")
-                        .set_pixels_frame(pixels.frame_mut());
+                        .get_palette()
+                        .unwrap_or_else(|| settings.read().expect("Lock poisoned").palette);
+                    let chip8 = chip8.read().expect("Lock poisoned");
+                    if chip8.disp_size() != disp_size {
+                        disp_size = chip8.disp_size();
+                        if let Err(err) = pixels.resize_buffer(disp_size.0 as u32, disp_size.1 as u32) {
+                            error!("On buffer resize, {}", err);
+                            elwt.exit();
+                            return;
+                        }
+                    }
+                    chip8.set_pixels_frame(pixels.frame_mut(), &palette);
+                    drop(chip8);
 
                     // Prepare egui
                     framework.prepare(&window);
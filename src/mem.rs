@@ -1,65 +1,304 @@
 use crate::{
-    errors::InvalidAccessError,
-    systems::{CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN, CHIP8_DISP_HEIGHT, CHIP8_DISP_WIDTH},
+    errors::{Chip8Fault, SaveStateError},
+    savestate::{StateReader, StateWriter},
+    systems::{
+        Platform, CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN, CHIP8_DISP_HEIGHT, CHIP8_DISP_WIDTH,
+        SCHIP_DISP_HEIGHT, SCHIP_DISP_WIDTH,
+    },
 };
-use anyhow::{Result, anyhow, Context};
+use anyhow::{Result, anyhow};
 
 pub trait Memory16Bit {
+    // Reads memory without it counting as an access, e.g. to display it
     fn get(&self, addr: u16, len: u16) -> Result<&[u8]>;
+    // Reads memory on behalf of the emulated program
+    fn read(&mut self, addr: u16, len: u16) -> Result<&[u8]>;
     fn set(&mut self, addr: u16, content: &[u8]) -> Result<()>;
     fn set_byte(&mut self, addr: u16, content: u8) -> Result<()>;
     fn dump(&self) -> &[u8];
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u16,
+    pub len: u16,
+    pub kind: AccessKind,
+}
+
 #[derive(Clone)]
 pub struct Chip8Mem {
-    ram: [u8; 4096],
+    ram: Vec<u8>,
+    // The COSMAC VIP maps its (only) display plane in RAM (at CHIP8_DISP_BUF_ADDR), later
+    // platforms don't, and need more room for the hi-res mode anyway. Empty in the first case.
+    planes: Vec<Vec<u8>>,
+    selected_planes: u8,
+    hires: bool,
+    // Reads and writes done since the last `clear_accesses`, for watchpoints
+    accesses: Vec<MemAccess>,
+    // Writes done during the current frame and the previous one, for memory viewers
+    frame_writes: Vec<MemAccess>,
+    last_frame_writes: Vec<MemAccess>,
 }
 
 impl Chip8Mem {
-    pub fn new() -> Self {
-        Self { ram: [0; 4096] } // 4K ram
+    pub fn new(platform: Platform) -> Self {
+        let (ram_size, plane_count) = match platform {
+            Platform::Chip8 => (0x1000, 0), // 4K ram
+            Platform::SChip => (0x1000, 1),
+            Platform::XoChip => (0x10000, 2), // 64K ram
+        };
+        Self {
+            ram: vec![0; ram_size],
+            planes: vec![vec![0; CHIP8_DISP_BUF_LEN as usize]; plane_count],
+            selected_planes: 0b01,
+            hires: false,
+            accesses: Vec::new(),
+            frame_writes: Vec::new(),
+            last_frame_writes: Vec::new(),
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_blob(&self.ram);
+        writer.put_bool(self.hires);
+        writer.put_u8(self.selected_planes);
+        writer.put_u8(self.planes.len() as u8);
+        self.planes.iter().for_each(|plane| writer.put_blob(plane));
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Self> {
+        let ram = reader.get_blob()?.to_vec();
+        let hires = reader.get_bool()?;
+        let selected_planes = reader.get_u8()?;
+        let plane_count = reader.get_u8()?;
+        let planes = (0..plane_count)
+            .map(|_| Ok(reader.get_blob()?.to_vec()))
+            .collect::<Result<Vec<_>>>()?;
+        let mem = Self {
+            ram,
+            planes,
+            selected_planes,
+            hires,
+            accesses: Vec::new(),
+            frame_writes: Vec::new(),
+            last_frame_writes: Vec::new(),
+        };
+
+        let (width, height) = mem.disp_size();
+        if ![0x1000, 0x10000].contains(&mem.ram.len())
+            || mem.plane_count() > 2
+            || mem.selected_planes >> mem.plane_count() != 0
+            || (mem.planes.is_empty() && mem.hires)
+            || mem.planes.iter().any(|plane| plane.len() != width as usize * height as usize / 8)
+        {
+            return Err(anyhow!(SaveStateError::new("inconsistent memory layout")));
+        }
+        Ok(mem)
+    }
+
+    pub fn get_accesses(&self) -> &[MemAccess] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub fn get_last_frame_writes(&self) -> &[MemAccess] {
+        &self.last_frame_writes
+    }
+
+    // To be called on each vblank
+    pub fn end_frame(&mut self) {
+        self.last_frame_writes = std::mem::take(&mut self.frame_writes);
+    }
+
+    fn record_write(&mut self, addr: u16, len: u16) {
+        let access = MemAccess {
+            addr,
+            len,
+            kind: AccessKind::Write,
+        };
+        self.accesses.push(access);
+        self.frame_writes.push(access);
+    }
+
+    pub fn disp_size(&self) -> (u16, u16) {
+        if self.hires {
+            (SCHIP_DISP_WIDTH, SCHIP_DISP_HEIGHT)
+        } else {
+            (CHIP8_DISP_WIDTH, CHIP8_DISP_HEIGHT)
+        }
+    }
+
+    pub fn plane_count(&self) -> usize {
+        self.planes.len().max(1)
+    }
+
+    pub fn disp_buf(&self, plane: usize) -> &[u8] {
+        if self.planes.is_empty() {
+            &self.ram[CHIP8_DISP_BUF_ADDR as usize..][..CHIP8_DISP_BUF_LEN as usize]
+        } else {
+            &self.planes[plane]
+        }
+    }
+
+    fn disp_buf_mut(&mut self, plane: usize) -> &mut [u8] {
+        if self.planes.is_empty() {
+            &mut self.ram[CHIP8_DISP_BUF_ADDR as usize..][..CHIP8_DISP_BUF_LEN as usize]
+        } else {
+            &mut self.planes[plane]
+        }
+    }
+
+    // Planes affected by drawing, clearing and scrolling
+    fn selected_planes(&self) -> Vec<usize> {
+        (0..self.plane_count())
+            .filter(|plane| self.selected_planes & (1 << plane) != 0)
+            .collect()
+    }
+
+    // Planes that don't exist are ignored
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & ((1 << self.plane_count()) - 1);
+    }
+
+    // Switching resolution clears the screen, as most SCHIP programs expect. Ignored when the
+    // display buffer is mapped in RAM, as on the original CHIP-8.
+    pub fn set_hires(&mut self, hires: bool) {
+        if self.planes.is_empty() {
+            return;
+        }
+        self.hires = hires;
+        let (width, height) = self.disp_size();
+        self.planes
+            .iter_mut()
+            .for_each(|plane| *plane = vec![0; width as usize * height as usize / 8]);
+    }
+
+    pub fn clear_disp(&mut self) {
+        for plane in self.selected_planes() {
+            self.disp_buf_mut(plane).fill(0);
+        }
+        if self.planes.is_empty() {
+            self.record_write(CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN);
+        }
     }
+
+    fn pixel(&self, plane: usize, x: usize, y: usize) -> bool {
+        let width = self.disp_size().0 as usize;
+        self.disp_buf(plane)[y * width / 8 + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    // Drawing on a display buffer mapped in RAM counts as writing to it, for watchpoints
+    fn set_pixel(&mut self, plane: usize, x: usize, y: usize, on: bool) {
+        let width = self.disp_size().0 as usize;
+        let offset = y * width / 8 + x / 8;
+        let byte = &mut self.disp_buf_mut(plane)[offset];
+        if on {
+            *byte |= 0x80 >> (x % 8);
+        } else {
+            *byte &= !(0x80 >> (x % 8));
+        }
+        if self.planes.is_empty() {
+            self.record_write(CHIP8_DISP_BUF_ADDR + offset as u16, 1);
+        }
+    }
+
+    // Moves the selected planes by (dx, dy) pixels, filling the uncovered area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.disp_size();
+        let (width, height) = (width as isize, height as isize);
+        for plane in self.selected_planes() {
+            let old = self.disp_buf(plane).to_vec();
+            for y in 0..height {
+                for x in 0..width {
+                    let (src_x, src_y) = (x - dx, y - dy);
+                    let on = (0..width).contains(&src_x)
+                        && (0..height).contains(&src_y)
+                        && old[(src_y * width + src_x) as usize / 8] & (0x80 >> (src_x % 8)) != 0;
+                    self.set_pixel(plane, x as usize, y as usize, on);
+                }
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: u8) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: u8) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_left(&mut self, n: u8) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    pub fn scroll_right(&mut self, n: u8) {
+        self.scroll(n as isize, 0);
+    }
+
+    // Number of bytes a sprite spans, given the planes currently selected
+    pub fn sprite_len(&self, wide: bool, height: u8) -> u16 {
+        let plane_len = if wide { 32 } else { height as u16 };
+        plane_len * self.selected_planes().len() as u16
+    }
+
+    // Draws a sprite 8 pixels wide (or 16 if `wide`, 2 bytes per line) on each selected plane, the
+    // data for each plane following one another in `sprite`, and returns whether any pixel got
+    // turned off.
     pub fn load_sprite(
         &mut self,
         sprite: &[u8],
+        wide: bool,
         x_uncapped: u8,
         y_uncapped: u8,
-        n: u8,
+        clipping: bool,
     ) -> Result<bool> {
-        let x = x_uncapped & (CHIP8_DISP_WIDTH as u8 - 1);
-        let y = y_uncapped & (CHIP8_DISP_HEIGHT as u8 - 1);
-        let mut fb = self
-            .get(CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN)
-            .context("Chip8 display buffer badly defined")?
-            .to_owned();
+        let (width, height) = self.disp_size();
+        let (width, height) = (width as usize, height as usize);
+        let x = x_uncapped as usize % width;
+        let y = y_uncapped as usize % height;
+        let sprite_width = if wide { 16 } else { 8 };
+        let planes = self.selected_planes();
         let mut flag = false;
-        fb.iter_mut()
-            .enumerate()
-            .filter(|(i, _b)| {
-                let fb_x = i % (CHIP8_DISP_WIDTH as usize / 8);
-                let fb_y = i / (CHIP8_DISP_WIDTH as usize / 8);
-                x as usize / 8 <= fb_x
-                    && fb_x <= (x as usize / 8) + 1
-                    && y as usize <= fb_y
-                    && fb_y < (y + n) as usize
-            })
-            .enumerate()
-            .for_each(|(j, (_i, b))| {
-                if (x as usize) / 8 == 7 {
-                    *b ^= sprite[j].checked_shr((x % 8).into()).unwrap_or(0);
-                    flag |= *b ^ (sprite[j].checked_shr((x % 8).into()).unwrap_or(0)) > 0;
-                    return;
+        for (i, plane) in planes.iter().enumerate() {
+            let plane_len = sprite.len() / planes.len();
+            let plane_sprite = &sprite[i * plane_len..(i + 1) * plane_len];
+            let lines: Vec<u16> = if wide {
+                plane_sprite
+                    .chunks_exact(2)
+                    .map(|line| u16::from_be_bytes([line[0], line[1]]))
+                    .collect()
+            } else {
+                plane_sprite.iter().map(|line| (*line as u16) << 8).collect()
+            };
+            for (row, line) in lines.iter().enumerate() {
+                if clipping && y + row >= height {
+                    break;
                 }
-                if j % 2 == 0 {
-                    *b ^= sprite[j / 2].checked_shr((x % 8).into()).unwrap_or(0);
-                    flag |= *b ^ (sprite[j / 2].checked_shr((x % 8).into()).unwrap_or(0)) > 0;
-                } else {
-                    *b ^= sprite[j / 2].checked_shl((8 - (x % 8)).into()).unwrap_or(0);
-                    flag |= *b ^ (sprite[j / 2].checked_shl((8 - (x % 8)).into()).unwrap_or(0)) > 0;
+                let fb_y = (y + row) % height;
+                for col in 0..sprite_width {
+                    if clipping && x + col >= width {
+                        break;
+                    }
+                    if line & (0x8000 >> col) == 0 {
+                        continue;
+                    }
+                    let fb_x = (x + col) % width;
+                    let on = self.pixel(*plane, fb_x, fb_y);
+                    flag |= on;
+                    self.set_pixel(*plane, fb_x, fb_y, !on);
                 }
-            });
-        self.set(CHIP8_DISP_BUF_ADDR, &fb)?;
+            }
+        }
         Ok(flag)
     }
 }
@@ -69,22 +308,28 @@ impl Memory16Bit for Chip8Mem {
         let res = &self.ram.get(addr as usize..(addr as usize + len as usize));
         match res {
             Some(res_ok) => Ok(res_ok),
-            None => Err(anyhow!(InvalidAccessError::new(format!(
-                "Address 0x{:X} unreachable",
-                addr
-            )))),
+            None => Err(anyhow!(Chip8Fault::OutOfBounds { addr, len })),
         }
     }
 
+    fn read(&mut self, addr: u16, len: u16) -> Result<&[u8]> {
+        self.accesses.push(MemAccess {
+            addr,
+            len,
+            kind: AccessKind::Read,
+        });
+        self.get(addr, len)
+    }
+
     fn set(&mut self, addr: u16, content: &[u8]) -> Result<()> {
-        if addr as usize + content.len() - 1 > 0xFFF {
-            return Err(anyhow!(InvalidAccessError::new(format!(
-                "Cannot set 0x{:X} bytes starting from 0x{:03X}, too big for emulated memory !",
-                content.len(),
-                addr
-            ))));
+        if addr as usize + content.len() > self.ram.len() {
+            return Err(anyhow!(Chip8Fault::OutOfBounds {
+                addr,
+                len: content.len() as u16,
+            }));
         }
 
+        self.record_write(addr, content.len() as u16);
         let _ = &mut self.ram[addr as usize..addr as usize + content.len()]
             .iter_mut()
             .enumerate()
@@ -93,13 +338,11 @@ impl Memory16Bit for Chip8Mem {
     }
 
     fn set_byte(&mut self, addr: u16, content: u8) -> Result<()> {
-        if addr as usize > 0xFFF {
-            return Err(anyhow!(InvalidAccessError::new(format!(
-                "Cannot set a bytes starting from 0x{:03X}, too big for emulated memory !",
-                addr
-            ))));
+        if addr as usize >= self.ram.len() {
+            return Err(anyhow!(Chip8Fault::OutOfBounds { addr, len: 1 }));
         }
 
+        self.record_write(addr, 1);
         self.ram[addr as usize] = content;
         Ok(())
     }
@@ -0,0 +1,236 @@
+use {
+    anyhow::{Result, anyhow},
+    sha1_smol::Sha1,
+    std::io::Write,
+};
+
+use crate::{
+    errors::MovieError,
+    keypad::Keypad,
+    savestate::{StateReader, StateWriter},
+    systems::{Chip8, Platform, Quirks, System},
+};
+
+// Input movies are a header describing how the machine was started, followed by the keypad state
+// of each frame until the end of the file, so that they can be written as the emulation goes.
+// Frames can be run in several goes (e.g. when the debugger pauses within one), the keypad state
+// staying the same until they end.
+const MOVIE_MAGIC: &[u8; 4] = b"RC8M";
+const MOVIE_VERSION: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieHeader {
+    pub program_sha1: [u8; 20],
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+}
+
+impl MovieHeader {
+    // `chip8` has to be freshly initialized, for the movie to start from the same state
+    pub fn new(program_data: &[u8], chip8: &Chip8, cycles_per_frame: u32) -> Self {
+        Self {
+            program_sha1: Sha1::from(program_data).digest().bytes(),
+            platform: chip8.get_platform(),
+            quirks: chip8.get_quirks(),
+            seed: chip8.get_seed(),
+            cycles_per_frame,
+        }
+    }
+
+    // Machine the movie has to be played back on, `program_data` still to be loaded
+    pub fn init_system(&self) -> Chip8 {
+        Chip8::init(self.platform, self.quirks, Some(self.seed))
+    }
+
+    pub fn check_program(&self, program_data: &[u8]) -> Result<()> {
+        if Sha1::from(program_data).digest().bytes() != self.program_sha1 {
+            return Err(anyhow!(MovieError::new(
+                "the movie was recorded with another program"
+            )));
+        }
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_magic(MOVIE_MAGIC, MOVIE_VERSION);
+        writer.put_bytes(&self.program_sha1);
+        writer.put_u8(self.platform as u8);
+        writer.put_u8(self.quirks.to_bits());
+        writer.put_u64(self.seed);
+        writer.put_u32(self.cycles_per_frame);
+        writer.finish()
+    }
+}
+
+pub struct MovieRecorder<W: Write> {
+    out: W,
+    // Keypad state of the frame being run
+    keypad: Option<Keypad>,
+}
+
+impl<W: Write> MovieRecorder<W> {
+    pub fn new(mut out: W, header: &MovieHeader) -> Result<Self> {
+        out.write_all(&header.to_bytes())?;
+        Ok(Self { out, keypad: None })
+    }
+
+    // Keypad state to give to the frame about to be run : `input`, unless the frame was started
+    // with another one
+    pub fn keypad(&mut self, input: Keypad) -> Keypad {
+        *self.keypad.get_or_insert(input)
+    }
+
+    // To be called once the frame ended, recording its keypad state
+    pub fn end_frame(&mut self) -> Result<()> {
+        if let Some(keypad) = self.keypad.take() {
+            self.out.write_all(&keypad.0.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<Keypad>,
+}
+
+impl Movie {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = StateReader::with_magic(data, MOVIE_MAGIC, MOVIE_VERSION)?;
+        let program_sha1 = reader.get_array()?;
+        let platform_id = reader.get_u8()?;
+        let Some(platform) = Platform::from_id(platform_id) else {
+            return Err(anyhow!(MovieError::new(format!(
+                "unknown platform {}",
+                platform_id
+            ))));
+        };
+        let quirks = Quirks::from_bits(reader.get_u8()?);
+        let seed = reader.get_u64()?;
+        let cycles_per_frame = reader.get_u32()?;
+
+        let mut frames = Vec::new();
+        while !reader.is_at_end() {
+            frames.push(Keypad(reader.get_u16()?));
+        }
+
+        Ok(Self {
+            header: MovieHeader {
+                program_sha1,
+                platform,
+                quirks,
+                seed,
+                cycles_per_frame,
+            },
+            frames,
+        })
+    }
+}
+
+pub struct MoviePlayer {
+    frames: std::vec::IntoIter<Keypad>,
+    // Keypad state of the frame being run
+    keypad: Option<Keypad>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        Self {
+            frames: movie.frames.into_iter(),
+            keypad: None,
+        }
+    }
+
+    // Keypad state to give to the frame about to be run, none once the movie ended
+    pub fn keypad(&mut self) -> Option<Keypad> {
+        if self.keypad.is_none() {
+            self.keypad = self.frames.next();
+        }
+        self.keypad
+    }
+
+    // To be called once the frame ended, moving on to the next one
+    pub fn end_frame(&mut self) {
+        self.keypad = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{breakpoints::Breakpoint, debugger::Debugger, systems::Register};
+
+    // Counts the instructions run in V3, and in V2 the frames' key checks finding 5 held
+    const PROGRAM: [u8; 12] = [
+        0x61, 0x05, 0xE1, 0x9E, 0x12, 0x08, 0x72, 0x01, 0x73, 0x01, 0x12, 0x02,
+    ];
+    const CYCLES_PER_FRAME: u32 = 7;
+    const FRAMES: usize = 30;
+
+    fn start(header: &MovieHeader) -> Chip8 {
+        let mut chip8 = header.init_system();
+        chip8.load_program(&PROGRAM).unwrap();
+        chip8
+    }
+
+    // Plays the movie back, breaking within frames if `breakpoint` is given
+    fn play(movie: Movie, breakpoint: Option<Breakpoint>) -> Chip8 {
+        let mut chip8 = start(&movie.header);
+        let mut debugger = Debugger::new();
+        if let Some(breakpoint) = breakpoint {
+            debugger.get_breakpoints_mut().add(breakpoint);
+        }
+        let mut player = MoviePlayer::new(movie);
+        while let Some(keypad) = player.keypad() {
+            chip8.set_keypad(keypad);
+            if debugger.run_frame(&mut chip8, CYCLES_PER_FRAME).unwrap() {
+                player.end_frame();
+            }
+            debugger.resume();
+        }
+        chip8
+    }
+
+    #[test]
+    fn record_and_play() {
+        let header = MovieHeader::new(
+            &PROGRAM,
+            &Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0)),
+            CYCLES_PER_FRAME,
+        );
+        let mut chip8 = start(&header);
+        let mut recorder = MovieRecorder::new(Vec::new(), &header).unwrap();
+        let mut debugger = Debugger::new();
+        // Breaking on each key check, the keypad changing before the frame goes on
+        let breakpoint = Breakpoint::Pc(0x204);
+        debugger.get_breakpoints_mut().add(breakpoint);
+        let (mut calls, mut frames) = (0, 0);
+        while frames < FRAMES {
+            let mut input = Keypad::default();
+            input.set(0x5, calls % 3 == 0);
+            chip8.set_keypad(recorder.keypad(input));
+            if debugger.run_frame(&mut chip8, CYCLES_PER_FRAME).unwrap() {
+                recorder.end_frame().unwrap();
+                frames += 1;
+            }
+            debugger.resume();
+            calls += 1;
+        }
+        assert!(calls > FRAMES);
+        assert!(chip8.get_register(Register::V(2)) > 0);
+
+        let movie = Movie::parse(&recorder.out).unwrap();
+        assert_eq!(movie.header, header);
+        assert_eq!(movie.frames.len(), FRAMES);
+        for breakpoint in [None, Some(breakpoint)] {
+            let played = play(Movie::parse(&recorder.out).unwrap(), breakpoint);
+            assert_eq!(
+                played.save_state(),
+                chip8.save_state(),
+                "breaking on {breakpoint:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,904 @@
+use {
+    anyhow::{Result, anyhow},
+    std::collections::{HashMap, VecDeque},
+};
+
+use crate::{
+    errors::AsmError,
+    systems::{CHIP8_PC_START, Platform},
+};
+
+// Compiler for Octo, the assembly language most modern CHIP-8 programs are written in :
+// https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+// Supported are labels, `:=` and the other assignment operators, `if ... then`,
+// `if ... begin ... else ... end`, `loop ... while ... again`, `:const`, `:alias`, `:macro`,
+// `:calc`, `:byte`, `:pointer`, `:org`, `:next` and `:unpack`, for the three platforms. The
+// program starts with a jump to its `main` label.
+
+// Register clobbered by the `<`, `>`, `<=` and `>=` comparisons
+const COMPARE_TEMP: u16 = 0xF;
+const MAX_MACRO_DEPTH: usize = 64;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line_nb: usize,
+    // Macros being expanded, to catch recursive ones
+    depth: usize,
+}
+
+// Address operands written once their label gets defined
+enum FixupKind {
+    // Low 12 bits of an instruction (jumps, calls, `i :=`)
+    Addr12,
+    // A whole 16 bit word (`i := long`, `:pointer`)
+    Addr16,
+    // The `v0 :=` and `v1 :=` pair of `:unpack`
+    Unpack(u8),
+}
+
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    label: String,
+    line_nb: usize,
+}
+
+enum Control {
+    // Address of the jump skipping the block
+    If(u32),
+    Else(u32),
+    // Start address of the loop, and the jumps out of it
+    Loop(u32, Vec<u32>),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    platform: Platform,
+    tokens: VecDeque<Token>,
+    line_nb: usize,
+    rom: Vec<u8>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    control: Vec<Control>,
+}
+
+pub fn compile(source: &str, platform: Platform) -> Result<Vec<u8>> {
+    let mut compiler = Compiler::new(source, platform);
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+fn number(word: &str) -> Option<f64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    } as f64;
+    Some(if negative { -value } else { value })
+}
+
+fn register(word: &str) -> Option<u16> {
+    match word.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|reg| reg as u16),
+        _ => None,
+    }
+}
+
+impl Compiler {
+    fn new(source: &str, platform: Platform) -> Self {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                line.split('#')
+                    .next()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(move |text| Token {
+                        text: text.to_string(),
+                        line_nb: i + 1,
+                        depth: 0,
+                    })
+            })
+            .collect();
+        Self {
+            platform,
+            tokens,
+            line_nb: 0,
+            rom: Vec::new(),
+            here: CHIP8_PC_START as u32,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            control: Vec::new(),
+        }
+    }
+
+    fn error(&self, reason: impl ToString) -> anyhow::Error {
+        anyhow!(AsmError::new(format!(
+            "line {} : {}",
+            self.line_nb,
+            reason.to_string()
+        )))
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error("unexpected end of file"))?;
+        self.line_nb = token.line_nb;
+        Ok(token)
+    }
+
+    fn next(&mut self) -> Result<String> {
+        self.next_token().map(|token| token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(self.error(format!("expected `{expected}`, got `{token}`")));
+        }
+        Ok(())
+    }
+
+    fn require(&self, platforms: &[Platform], what: &str) -> Result<()> {
+        if !platforms.contains(&self.platform) {
+            return Err(self.error(format!("`{what}` isn't available on {:?}", self.platform)));
+        }
+        Ok(())
+    }
+
+    fn require_schip(&self, what: &str) -> Result<()> {
+        self.require(&[Platform::SChip, Platform::XoChip], what)
+    }
+
+    fn require_xochip(&self, what: &str) -> Result<()> {
+        self.require(&[Platform::XoChip], what)
+    }
+
+    fn check_name(&self, name: &str) -> Result<()> {
+        let defined = self.labels.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name);
+        if defined || number(name).is_some() || register(name).is_some() {
+            return Err(self.error(format!("`{name}` can't be redefined")));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, byte: u8) -> Result<()> {
+        let offset = (self.here - CHIP8_PC_START as u32) as usize;
+        let max_len = (self.platform.stack_base() - CHIP8_PC_START) as usize;
+        if offset >= max_len {
+            return Err(self.error(format!("program too large, over {max_len} B")));
+        }
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn inst(&mut self, opcode: u16) -> Result<()> {
+        opcode
+            .to_be_bytes()
+            .into_iter()
+            .try_for_each(|byte| self.byte(byte))
+    }
+
+    // 1NNN to a block's boundary or to `main`, which can't be further than 12 bits either
+    fn jump(&self, target: u32) -> Result<u16> {
+        if target > 0xFFF {
+            return Err(self.error(format!("jump to 0x{target:X} out of the 12 bit range")));
+        }
+        Ok(0x1000 | target as u16)
+    }
+
+    fn patch_jump(&mut self, at: u32, target: u32) -> Result<()> {
+        let offset = (at - CHIP8_PC_START as u32) as usize;
+        let opcode = self.jump(target)?;
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+        Ok(())
+    }
+
+    fn reg(&mut self) -> Result<u16> {
+        let token = self.next()?;
+        self.as_reg(&token)
+            .ok_or_else(|| self.error(format!("expected a register, got `{token}`")))
+    }
+
+    fn as_reg(&self, token: &str) -> Option<u16> {
+        register(token).or_else(|| self.aliases.get(token).copied())
+    }
+
+    // A value known at this point : number, constant, already defined label or `:calc` result
+    fn value(&mut self, token: &str) -> Result<f64> {
+        if token == "{" {
+            return self.calc_block();
+        }
+        number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|addr| *addr as f64))
+            .ok_or_else(|| self.error(format!("undefined value `{token}`")))
+    }
+
+    fn sized_value(&mut self, bits: u32) -> Result<u16> {
+        let token = self.next()?;
+        let value = self.value(&token)? as i64;
+        if value >= 1 << bits || value < -(1 << (bits - 1)) {
+            return Err(self.error(format!("`{token}` = {value} doesn't fit in {bits} bits")));
+        }
+        Ok((value as u16) & ((1u32 << bits) - 1) as u16)
+    }
+
+    // An address, possibly of a label defined later on
+    fn addr(&mut self, kind: FixupKind, offset: usize) -> Result<u16> {
+        let token = self.next()?;
+        if number(&token).is_some() || self.constants.contains_key(&token) || token == "{" {
+            return self.value(&token).map(|value| value as u16);
+        }
+        if let Some(addr) = self.labels.get(&token) {
+            return Ok(*addr);
+        }
+        self.fixups.push(Fixup {
+            offset,
+            kind,
+            label: token,
+            line_nb: self.line_nb,
+        });
+        Ok(0)
+    }
+
+    fn offset(&self) -> usize {
+        (self.here - CHIP8_PC_START as u32) as usize
+    }
+
+    fn addr12(&mut self, opcode: u16) -> Result<()> {
+        let offset = self.offset();
+        let addr = self.addr(FixupKind::Addr12, offset)?;
+        if addr > 0xFFF {
+            return Err(self.error(format!("address 0x{addr:X} out of the 12 bit range")));
+        }
+        self.inst(opcode | addr)
+    }
+
+    fn compile(&mut self) -> Result<()> {
+        // Replaced once `main` is known
+        self.inst(0x1000)?;
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.control.is_empty() {
+            return Err(self.error("unterminated `begin` or `loop` block"));
+        }
+        let Some(main) = self.labels.get("main").copied() else {
+            return Err(self.error("the program has no `main` label"));
+        };
+        self.patch_jump(CHIP8_PC_START as u32, main as u32)?;
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line_nb = fixup.line_nb;
+            let addr = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| self.error(format!("undefined label `{}`", fixup.label)))?;
+            if addr > 0xFFF && !matches!(fixup.kind, FixupKind::Addr16) {
+                return Err(self.error(format!("`{}` out of the 12 bit range", fixup.label)));
+            }
+            let bytes = &mut self.rom[fixup.offset..];
+            match fixup.kind {
+                FixupKind::Addr12 => {
+                    bytes[0] |= (addr >> 8) as u8;
+                    bytes[1] = addr as u8;
+                }
+                FixupKind::Addr16 => bytes[..2].copy_from_slice(&addr.to_be_bytes()),
+                FixupKind::Unpack(nibble) => {
+                    bytes[1] = (nibble << 4) | (addr >> 8) as u8;
+                    bytes[3] = addr as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let Token {
+            text: token, depth, ..
+        } = self.next_token()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name, self.here as u16);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let token = self.next()?;
+                let value = self.value(&token)?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.expect("{")?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let reg = self.reg()?;
+                self.aliases.insert(name, reg);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let byte = self.sized_value(8)?;
+                self.byte(byte as u8)?;
+            }
+            ":pointer" => {
+                let offset = self.offset();
+                let addr = self.addr(FixupKind::Addr16, offset)?;
+                self.inst(addr)?;
+            }
+            ":org" => {
+                let addr = self.sized_value(16)? as u32;
+                if addr < CHIP8_PC_START as u32 {
+                    return Err(
+                        self.error(format!("cannot place code at 0x{addr:X}, before 0x200"))
+                    );
+                }
+                self.here = addr;
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name, self.here as u16 + 1);
+            }
+            ":unpack" => {
+                let nibble = self.sized_value(4)? as u8;
+                let offset = self.offset();
+                let addr = self.addr(FixupKind::Unpack(nibble), offset)?;
+                // Only 12 bits fit along with the nibble, as in Octo
+                if addr > 0xFFF {
+                    return Err(self.error(format!("address 0x{addr:X} out of the 12 bit range")));
+                }
+                self.inst(0x6000 | (nibble as u16) << 4 | addr >> 8)?;
+                self.inst(0x6100 | (addr & 0xFF))?;
+            }
+            // Debugger hints, without any effect on the program
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+
+            "clear" => self.inst(0x00E0)?,
+            "return" | ";" => self.inst(0x00EE)?,
+            "scroll-down" => {
+                self.require_schip(&token)?;
+                let n = self.sized_value(4)?;
+                self.inst(0x00C0 | n)?
+            }
+            "scroll-up" => {
+                self.require_xochip(&token)?;
+                let n = self.sized_value(4)?;
+                self.inst(0x00D0 | n)?
+            }
+            "scroll-right" => self.require_schip(&token).and_then(|_| self.inst(0x00FB))?,
+            "scroll-left" => self.require_schip(&token).and_then(|_| self.inst(0x00FC))?,
+            "exit" => self.require_schip(&token).and_then(|_| self.inst(0x00FD))?,
+            "lores" => self.require_schip(&token).and_then(|_| self.inst(0x00FE))?,
+            "hires" => self.require_schip(&token).and_then(|_| self.inst(0x00FF))?,
+            "audio" => self
+                .require_xochip(&token)
+                .and_then(|_| self.inst(0xF002))?,
+            "plane" => {
+                self.require_xochip(&token)?;
+                let n = self.sized_value(4)?;
+                self.inst(0xF001 | n << 8)?
+            }
+            "native" => self.addr12(0x0000)?,
+            "jump" => self.addr12(0x1000)?,
+            "jump0" => self.addr12(0xB000)?,
+            "bcd" => {
+                let x = self.reg()?;
+                self.inst(0xF033 | x << 8)?
+            }
+            "save" | "load" => {
+                let x = self.reg()?;
+                if self.peek() == Some("-") {
+                    self.require_xochip(&format!("{token} vx - vy"))?;
+                    self.next()?;
+                    let y = self.reg()?;
+                    let op = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.inst(op | x << 8 | y << 4)?
+                } else {
+                    let op = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.inst(op | x << 8)?
+                }
+            }
+            "saveflags" | "loadflags" => {
+                self.require_schip(&token)?;
+                let x = self.reg()?;
+                let op = if token == "saveflags" { 0xF075 } else { 0xF085 };
+                self.inst(op | x << 8)?
+            }
+            "sprite" => {
+                let x = self.reg()?;
+                let y = self.reg()?;
+                let n = self.sized_value(4)?;
+                if n == 0 {
+                    self.require_schip("sprite vx vy 0")?;
+                }
+                self.inst(0xD000 | x << 8 | y << 4 | n)?
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()?;
+                let op = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => {
+                        self.require_xochip("pitch")?;
+                        0xF03A
+                    }
+                };
+                self.inst(op | x << 8)?
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.control.pop() {
+                Some(Control::If(skip_at)) => {
+                    let jump_at = self.here;
+                    self.inst(0x1000)?;
+                    self.patch_jump(skip_at, self.here)?;
+                    self.control.push(Control::Else(jump_at));
+                }
+                _ => return Err(self.error("`else` without `if ... begin`")),
+            },
+            "end" => match self.control.pop() {
+                Some(Control::If(jump_at) | Control::Else(jump_at)) => {
+                    self.patch_jump(jump_at, self.here)?
+                }
+                _ => return Err(self.error("`end` without `if ... begin`")),
+            },
+            "loop" => self.control.push(Control::Loop(self.here, Vec::new())),
+            "while" => {
+                // Leaves the loop unless the condition holds
+                self.condition(true)?;
+                let jump_at = self.here;
+                self.inst(0x1000)?;
+                match self
+                    .control
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Control::Loop(..)))
+                {
+                    Some(Control::Loop(_, breaks)) => breaks.push(jump_at),
+                    _ => return Err(self.error("`while` outside of a loop")),
+                }
+            }
+            "again" => match self.control.pop() {
+                Some(Control::Loop(start, breaks)) => {
+                    self.inst(self.jump(start)?)?;
+                    breaks
+                        .into_iter()
+                        .try_for_each(|jump_at| self.patch_jump(jump_at, self.here))?;
+                }
+                _ => return Err(self.error("`again` without `loop`")),
+            },
+
+            _ if self.as_reg(&token).is_some() => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token, depth)?,
+            _ if number(&token).is_some()
+                || self.constants.contains_key(&token)
+                || token == "{" =>
+            {
+                let value = self.value(&token)? as i64;
+                if !(-128..=255).contains(&value) {
+                    return Err(self.error(format!("`{token}` = {value} doesn't fit in a byte")));
+                }
+                self.byte(value as u8)?
+            }
+            _ if token.starts_with(':') => {
+                return Err(self.error(format!("unknown directive `{token}`")));
+            }
+            // Anything else is a call to a subroutine, possibly defined later on
+            _ => {
+                self.tokens.push_front(Token {
+                    text: token,
+                    line_nb: self.line_nb,
+                    depth,
+                });
+                self.addr12(0x2000)?
+            }
+        }
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.reg()?;
+                    self.inst(0xF029 | x << 8)
+                }
+                Some("bighex") => {
+                    self.require_schip("i := bighex")?;
+                    self.next()?;
+                    let x = self.reg()?;
+                    self.inst(0xF030 | x << 8)
+                }
+                Some("long") => {
+                    self.require_xochip("i := long")?;
+                    self.next()?;
+                    self.inst(0xF000)?;
+                    let offset = self.offset();
+                    let addr = self.addr(FixupKind::Addr16, offset)?;
+                    self.inst(addr)
+                }
+                _ => self.addr12(0xA000),
+            },
+            "+=" => {
+                let x = self.reg()?;
+                self.inst(0xF01E | x << 8)
+            }
+            _ => Err(self.error(format!("unknown operator `i {op}`"))),
+        }
+    }
+
+    fn register_statement(&mut self, token: &str) -> Result<()> {
+        let x = self.as_reg(token).unwrap_or_default();
+        let op = self.next()?;
+        let operand = self.next()?;
+        let y = self.as_reg(&operand);
+        let opcode = match (op.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            (":=", None) => match operand.as_str() {
+                "random" => 0xC000 | x << 8 | self.sized_value(8)?,
+                "delay" => 0xF007 | x << 8,
+                "key" => 0xF00A | x << 8,
+                _ => {
+                    self.tokens.push_front(Token {
+                        text: operand,
+                        line_nb: self.line_nb,
+                        depth: 0,
+                    });
+                    0x6000 | x << 8 | self.sized_value(8)?
+                }
+            },
+            ("+=", None) | ("-=", None) => {
+                self.tokens.push_front(Token {
+                    text: operand,
+                    line_nb: self.line_nb,
+                    depth: 0,
+                });
+                let value = self.sized_value(8)?;
+                let value = if op == "-=" {
+                    value.wrapping_neg() & 0xFF
+                } else {
+                    value
+                };
+                0x7000 | x << 8 | value
+            }
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            _ => return Err(self.error(format!("invalid operation `{token} {op} {operand}`"))),
+        };
+        self.inst(opcode)
+    }
+
+    // Emits the instructions skipping the next one when the condition is false, or when it's true
+    // if `skip_if_true`
+    fn condition(&mut self, skip_if_true: bool) -> Result<()> {
+        let token = self.next()?;
+        let x = self
+            .as_reg(&token)
+            .ok_or_else(|| self.error(format!("expected a register, got `{token}`")))?;
+        let op = self.next()?;
+        if op == "key" || op == "-key" {
+            let skip_if_pressed = (op == "key") == skip_if_true;
+            return self.inst(if skip_if_pressed { 0xE09E } else { 0xE0A1 } | x << 8);
+        }
+        let operand = self.next()?;
+        let y = self.as_reg(&operand);
+        let value = |compiler: &mut Self| -> Result<u16> {
+            compiler.tokens.push_front(Token {
+                text: operand.clone(),
+                line_nb: compiler.line_nb,
+                depth: 0,
+            });
+            compiler.sized_value(8)
+        };
+        match (op.as_str(), y) {
+            ("==", Some(y)) => {
+                self.inst(if skip_if_true { 0x5000 } else { 0x9000 } | x << 8 | y << 4)
+            }
+            ("!=", Some(y)) => {
+                self.inst(if skip_if_true { 0x9000 } else { 0x5000 } | x << 8 | y << 4)
+            }
+            ("==", None) => {
+                let nn = value(self)?;
+                self.inst(if skip_if_true { 0x3000 } else { 0x4000 } | x << 8 | nn)
+            }
+            ("!=", None) => {
+                let nn = value(self)?;
+                self.inst(if skip_if_true { 0x4000 } else { 0x3000 } | x << 8 | nn)
+            }
+            ("<" | ">" | "<=" | ">=", _) => {
+                // vF := operand, then vF -= vX or vF =- vX leaves the borrow flag in vF
+                match y {
+                    Some(y) => self.inst(0x8000 | COMPARE_TEMP << 8 | y << 4)?,
+                    None => {
+                        let nn = value(self)?;
+                        self.inst(0x6000 | COMPARE_TEMP << 8 | nn)?
+                    }
+                }
+                let (sub, holds_if) = match op.as_str() {
+                    // flag = operand >= vX
+                    ">" => (0x5, 0),
+                    "<=" => (0x5, 1),
+                    // flag = vX >= operand
+                    "<" => (0x7, 0),
+                    _ => (0x7, 1),
+                };
+                self.inst(0x8000 | COMPARE_TEMP << 8 | x << 4 | sub)?;
+                let skip = if skip_if_true { 0x3000 } else { 0x4000 };
+                self.inst(skip | COMPARE_TEMP << 8 | holds_if)
+            }
+            _ => Err(self.error(format!("invalid condition `{token} {op} {operand}`"))),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        // The condition is only known to be followed by `then` or `begin` once parsed
+        let end = self
+            .tokens
+            .iter()
+            .position(|token| token.text == "then" || token.text == "begin")
+            .ok_or_else(|| self.error("`if` without `then` or `begin`"))?;
+        let block = self.tokens[end].text == "begin";
+        self.condition(block)?;
+        self.next()?;
+        if block {
+            self.control.push(Control::If(self.here));
+            self.inst(0x1000)?;
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "{" => break,
+                param => params.push(param.to_string()),
+            }
+        }
+        let mut body = Vec::new();
+        let mut nesting = 0;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error(format!("unterminated macro `{name}`")))?;
+            match token.text.as_str() {
+                "{" => nesting += 1,
+                "}" if nesting == 0 => break,
+                "}" => nesting -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str, depth: usize) -> Result<()> {
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(format!("macro `{name}` expands recursively")));
+        }
+        let param_count = self.macros[name].params.len();
+        let args = (0..param_count)
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>>>()?;
+        let line_nb = self.line_nb;
+        let Macro { params, body } = &self.macros[name];
+        let expansion: Vec<Token> = body
+            .iter()
+            .map(|token| Token {
+                text: match params.iter().position(|param| *param == token.text) {
+                    Some(i) => args[i].clone(),
+                    None => token.text.clone(),
+                },
+                line_nb,
+                depth: depth + 1,
+            })
+            .collect();
+        expansion
+            .into_iter()
+            .rev()
+            .for_each(|token| self.tokens.push_front(token));
+        Ok(())
+    }
+
+    // `:calc` expressions, once their opening brace is read. As in Octo, binary operators have no
+    // precedence and are evaluated from right to left, parentheses grouping terms.
+    fn calc_block(&mut self) -> Result<f64> {
+        let value = self.calc_expr()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expr(&mut self) -> Result<f64> {
+        let lhs = self.calc_term()?;
+        let op = match self.peek() {
+            Some(
+                op @ ("+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min"
+                | "max" | "<" | ">" | "<=" | ">=" | "==" | "!="),
+            ) => op.to_string(),
+            _ => return Ok(lhs),
+        };
+        self.next()?;
+        let rhs = self.calc_expr()?;
+        let (l, r) = (lhs as i64, rhs as i64);
+        let bool_value = |b: bool| if b { 1.0 } else { 0.0 };
+        Ok(match op.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" => (l << r) as f64,
+            ">>" => (l >> r) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool_value(lhs < rhs),
+            ">" => bool_value(lhs > rhs),
+            "<=" => bool_value(lhs <= rhs),
+            ">=" => bool_value(lhs >= rhs),
+            "==" => bool_value(lhs == rhs),
+            _ => bool_value(lhs != rhs),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64> {
+        let token = self.next()?;
+        Ok(match token.as_str() {
+            "(" => {
+                let value = self.calc_expr()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => (self.calc_term()? == 0.0) as u8 as f64,
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "tan" => self.calc_term()?.tan(),
+            "exp" => self.calc_term()?.exp(),
+            "log" => self.calc_term()?.ln(),
+            "floor" => self.calc_term()?.floor(),
+            "ceil" => self.calc_term()?.ceil(),
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => self.value(&token)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_chip8(source: &str) -> Vec<u8> {
+        compile(source, Platform::Chip8).unwrap()
+    }
+
+    fn assert_error(source: &str, platform: Platform, reason: &str) {
+        let err = compile(source, platform).unwrap_err().to_string();
+        assert!(err.contains(reason), "`{err}` doesn't contain `{reason}`");
+    }
+
+    #[test]
+    fn statements() {
+        let rom = compile_chip8(": main clear v0 := 5 v1 += v0 v2 -= 1 i := 0x300 sprite v0 v1 4 return");
+        assert_eq!(
+            rom,
+            [0x12, 0x02, 0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0x72, 0xFF, 0xA3, 0x00, 0xD0, 0x14, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let rom = compile_chip8(": main loop if v0 == 3 then v1 := 1 v0 += 1 while v0 != 8 again");
+        assert_eq!(
+            rom,
+            [0x12, 0x02, 0x40, 0x03, 0x61, 0x01, 0x70, 0x01, 0x40, 0x08, 0x12, 0x0E, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn macros_and_constants() {
+        let rom = compile_chip8(
+            ":const SPEED 3 :macro step REG { REG += SPEED } : main step v2 :calc DOUBLE { SPEED * 2 } v3 := DOUBLE",
+        );
+        assert_eq!(rom, [0x12, 0x02, 0x72, 0x03, 0x63, 0x06]);
+    }
+
+    #[test]
+    fn forward_labels() {
+        // Labels used before being defined are patched in once the whole program is compiled
+        let rom = compile_chip8(": main jump later i := data :unpack 0xA data : later ; : data 0xFF");
+        assert_eq!(rom, [0x12, 0x02, 0x12, 0x0A, 0xA2, 0x0C, 0x60, 0xA2, 0x61, 0x0C, 0x00, 0xEE, 0xFF]);
+    }
+
+    #[test]
+    fn long_addresses() {
+        let rom = compile(": main i := long far :org 0x1000 : far 0xAA", Platform::XoChip).unwrap();
+        assert_eq!(&rom[2..6], [0xF0, 0x00, 0x10, 0x00]);
+        assert_eq!(rom[0x1000 - 0x200], 0xAA);
+        // Neither 12 bit operands nor `:unpack` fit addresses above 0xFFF
+        assert_error(": main jump far :org 0x1000 : far", Platform::XoChip, "`far` out of the 12 bit range");
+        assert_error(": main :unpack 0 far :org 0x1000 : far", Platform::XoChip, "`far` out of the 12 bit");
+        assert_error(":org 0x1000 : far : main :unpack 0 far", Platform::XoChip, "address 0x1000 out of the 12 bit");
+        // Nor the jumps of blocks, and of the start of the program
+        assert_error(": main\n:org 0x1000 loop\nagain", Platform::XoChip, "line 3 : jump to 0x1000 out of the 12 bit");
+        assert_error(": main\n:org 0xFFC loop\nwhile v0 == 0\nagain", Platform::XoChip, "line 4 : jump to 0x1002 out");
+        assert_error(": main\n:org 0xFFC if v0 == 0 begin\nend", Platform::XoChip, "line 3 : jump to 0x1000 out");
+        assert_error(":org 0x1000 : main", Platform::XoChip, "jump to 0x1000 out of the 12 bit range");
+    }
+
+    #[test]
+    fn errors() {
+        assert_error("v0 := 1", Platform::Chip8, "the program has no `main` label");
+        assert_error(": main jump nowhere", Platform::Chip8, "line 1 : undefined label `nowhere`");
+        assert_error(": main v0 := 256", Platform::Chip8, "doesn't fit in 8 bits");
+        assert_error(": main loop", Platform::Chip8, "unterminated");
+    }
+}
@@ -0,0 +1,274 @@
+use {
+    anyhow::Result,
+    rusty_chip8::{
+        breakpoints::Breakpoint,
+        debug::Screen,
+        debugger::Debugger,
+        disas::disas_mem,
+        mem::Memory16Bit,
+        systems::{Chip8, Register},
+    },
+    std::{
+        io::{BufRead, Write},
+        sync::mpsc::{self, Receiver},
+        time::{Duration, Instant},
+    },
+};
+
+const FRAME_DURATION: Duration = Duration::from_nanos(16_666_667); // 60Hz
+const DISAS_LINES: usize = 10;
+
+const HELP: &str = "\
+step [n]           execute n instructions (1 by default)
+continue           run until a breakpoint is hit, or a line is entered
+break [breakpoint] set a breakpoint (e.g. `200`, `vA == 10`, `op DXYN`), or list them
+watch addr [end]   break on writes to memory
+delete n           remove the nth breakpoint
+regs               print the registers
+mem addr len       dump memory
+disas [addr] [n]   disassemble n instructions from addr (pc by default)
+bt                 print the backtrace and call stack
+set reg value      set a register (v0-vF, i, pc, sp, dt or st)
+screen             draw the display
+quit               exit
+Numbers are hexadecimal, and an empty line repeats the last command.";
+
+// Lines typed on stdin, read from another thread for `continue` to be interruptible
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn parse_number(word: &str) -> Option<u16> {
+    u16::from_str_radix(word.trim_start_matches("0x"), 16).ok()
+}
+
+// Command prompt driving the CPU through a `Debugger`, for sessions without a window (e.g. over SSH)
+pub struct Repl {
+    chip8: Chip8,
+    debugger: Debugger,
+    cycles_per_frame: u32,
+    input: Receiver<String>,
+    // Line entered while running, executed once stopped
+    pending: Option<String>,
+}
+
+impl Repl {
+    pub fn new(chip8: Chip8, mut debugger: Debugger, cycles_per_frame: u32) -> Self {
+        debugger.pause();
+        Self {
+            chip8,
+            debugger,
+            cycles_per_frame,
+            input: spawn_stdin_reader(),
+            pending: None,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        println!("Type `help` for the list of commands");
+        self.print_location();
+        let mut last_command = String::new();
+        loop {
+            print!("(chip8) ");
+            std::io::stdout().flush()?;
+            let Some(line) = self.pending.take().or_else(|| self.input.recv().ok()) else {
+                return Ok(());
+            };
+            let line = match line.trim() {
+                "" => last_command.clone(),
+                line => line.to_string(),
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                ["quit" | "q"] => return Ok(()),
+                words => self.execute(words),
+            }
+            last_command = line;
+        }
+    }
+
+    fn execute(&mut self, words: &[&str]) {
+        let usage = || println!("Invalid command, see `help`");
+        match words {
+            ["help" | "h"] => println!("{HELP}"),
+            ["step" | "s" | "continue" | "c", ..] if self.debugger.is_halted() => {
+                println!("The program was halted by a fault, and can't be resumed")
+            }
+            ["step" | "s", count @ ..] if count.len() <= 1 => {
+                match count.first().map_or(Some(1), |count| parse_number(count)) {
+                    Some(count) => self.step(count),
+                    None => usage(),
+                }
+            }
+            ["continue" | "c"] => self.continue_running(),
+            ["break" | "b"] => {
+                for (i, breakpoint) in self.debugger.get_breakpoints().get().iter().enumerate() {
+                    println!("{i}: {breakpoint}");
+                }
+            }
+            ["break" | "b", breakpoint @ ..] => self.add_breakpoint(&breakpoint.join(" ")),
+            ["watch" | "w", range @ ..] if !range.is_empty() => {
+                self.add_breakpoint(&format!("write {}", range.join(" ")))
+            }
+            ["delete" | "d", index] => match index.parse() {
+                Ok(index) => {
+                    if self.debugger.get_breakpoints_mut().remove(index).is_none() {
+                        println!("No breakpoint {index}");
+                    }
+                }
+                Err(_) => usage(),
+            },
+            ["regs" | "r"] => println!("{}", self.chip8.get_state()),
+            ["mem" | "m", addr, len] => match (parse_number(addr), parse_number(len)) {
+                (Some(addr), Some(len)) => self.print_mem(addr, len),
+                _ => usage(),
+            },
+            ["disas" | "x", args @ ..] if args.len() <= 2 => {
+                let addr = args
+                    .first()
+                    .map_or(Some(self.chip8.get_state().pc), |addr| parse_number(addr));
+                let count = args
+                    .get(1)
+                    .map_or(Some(DISAS_LINES as u16), |count| parse_number(count));
+                match (addr, count) {
+                    (Some(addr), Some(count)) => self.print_disas(addr, count as usize),
+                    _ => usage(),
+                }
+            }
+            ["bt"] => {
+                println!("{}", self.chip8.get_backtrace());
+                for addr in self.chip8.get_call_stack().iter().rev() {
+                    println!("called from {addr:03x}");
+                }
+            }
+            ["set", reg, value] => match (Register::from_name(reg), parse_number(value)) {
+                (Some(reg), Some(value)) => {
+                    if let Err(err) = self.chip8.set_register(reg, value) {
+                        println!("{err}");
+                    }
+                }
+                _ => usage(),
+            },
+            ["screen"] => print!("{}", Screen(self.chip8.get_mem())),
+            _ => usage(),
+        }
+    }
+
+    fn add_breakpoint(&mut self, text: &str) {
+        match Breakpoint::parse(text) {
+            Ok(breakpoint) => self.debugger.get_breakpoints_mut().add(breakpoint),
+            Err(err) => println!("{err}"),
+        }
+    }
+
+    // Runs the debugger's current mode for a frame, returning whether to stop there
+    fn run_frame(&mut self) -> bool {
+        let hit_count = self.debugger.get_hit_count();
+        let fault_count = self.debugger.get_fault_count();
+        if let Err(err) = self
+            .debugger
+            .run_frame(&mut self.chip8, self.cycles_per_frame)
+        {
+            println!("{err}");
+            self.debugger.pause();
+            return true;
+        }
+        if let Some(hit) = self
+            .debugger
+            .get_hit()
+            .filter(|_| self.debugger.get_hit_count() != hit_count)
+        {
+            println!("Breakpoint hit : {hit}");
+            return true;
+        }
+        if let Some((fault, _)) = self
+            .debugger
+            .get_fault()
+            .filter(|_| self.debugger.get_fault_count() != fault_count)
+        {
+            println!("{fault}");
+            return true;
+        }
+        if self.chip8.is_halted() {
+            println!("Program exited");
+            self.debugger.pause();
+            return true;
+        }
+        false
+    }
+
+    fn step(&mut self, count: u16) {
+        for _ in 0..count {
+            self.debugger.step();
+            if self.run_frame() {
+                break;
+            }
+        }
+        self.print_location();
+    }
+
+    // Runs in real time, for timers to behave as they would in the GUI
+    fn continue_running(&mut self) {
+        self.debugger.resume();
+        let mut next_frame = Instant::now();
+        loop {
+            // Keeps running once stdin is closed
+            if let Ok(line) = self.input.try_recv() {
+                self.pending = Some(line).filter(|line| !line.trim().is_empty());
+                println!("Interrupted");
+                self.debugger.pause();
+                break;
+            }
+            if self.run_frame() || self.debugger.is_paused() {
+                break;
+            }
+            next_frame += FRAME_DURATION;
+            if let Some(delay) = next_frame.checked_duration_since(Instant::now()) {
+                std::thread::sleep(delay);
+            }
+        }
+        self.print_location();
+    }
+
+    fn print_location(&self) {
+        let pc = self.chip8.get_state().pc;
+        if let Some((addr, text)) = disas_mem(self.chip8.get_mem(), pc, 1).first() {
+            println!("{addr:03x}: {text}");
+        }
+    }
+
+    fn print_mem(&self, addr: u16, len: u16) {
+        let mem = self.chip8.get_mem();
+        let len = len.min((mem.dump().len() as u32).saturating_sub(addr as u32) as u16);
+        let Ok(bytes) = mem.get(addr, len) else {
+            println!("Out of memory bounds");
+            return;
+        };
+        for (i, line) in bytes.chunks(0x10).enumerate() {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+            println!("{:03x}: {}", addr as usize + i * 0x10, hex.join(" "));
+        }
+    }
+
+    fn print_disas(&self, addr: u16, count: usize) {
+        let pc = self.chip8.get_state().pc;
+        let instructions = disas_mem(self.chip8.get_mem(), addr, count);
+        if instructions.is_empty() {
+            println!("Out of memory bounds");
+        }
+        for (addr, text) in instructions {
+            let marker = if addr == pc { "->" } else { "  " };
+            println!("{marker} {addr:03x}: {text}");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+// Ring buffer of the last `depth` machine snapshots (as produced by `Chip8::save_state`), to play
+// the emulation backwards. Only the latest snapshot is kept whole, the previous ones being stored
+// as the difference with the one that follows: successive frames mostly differ by a few registers
+// and bytes of RAM, so these deltas are tiny once their runs of zeros are compressed.
+pub struct Rewind {
+    deltas: VecDeque<Vec<u8>>,
+    last: Option<Vec<u8>>,
+    depth: usize,
+}
+
+impl Rewind {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            deltas: VecDeque::new(),
+            last: None,
+            depth,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.last.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.last = None;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.depth == 0 {
+            return;
+        }
+        if let Some(last) = self.last.take() {
+            self.deltas.push_back(encode_delta(&state, &last));
+            if self.deltas.len() >= self.depth {
+                self.deltas.pop_front();
+            }
+        }
+        self.last = Some(state);
+    }
+
+    // Steps one snapshot back, returning the one pushed before the latest
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let prev = decode_delta(self.last.as_ref()?, &delta);
+        self.last = Some(prev.clone());
+        Some(prev)
+    }
+}
+
+// A delta is the length of the target followed by the XOR of both buffers (the shortest being
+// padded with zeros), stored as a sequence of (zeros count, literal count, literals).
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xored: Vec<u8> = (0..len)
+        .map(|i| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0))
+        .collect();
+
+    let mut delta = Vec::new();
+    put_varint(&mut delta, to.len());
+    let mut i = 0;
+    while i < len {
+        let zeros = xored[i..].iter().take_while(|b| **b == 0).count();
+        i += zeros;
+        let literals = xored[i..].iter().take_while(|b| **b != 0).count();
+        put_varint(&mut delta, zeros);
+        put_varint(&mut delta, literals);
+        delta.extend_from_slice(&xored[i..i + literals]);
+        i += literals;
+    }
+    delta
+}
+
+fn decode_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = get_varint(delta, &mut pos);
+    let mut xored = Vec::with_capacity(len);
+    while pos < delta.len() {
+        let zeros = get_varint(delta, &mut pos);
+        let literals = get_varint(delta, &mut pos);
+        xored.resize(xored.len() + zeros, 0);
+        xored.extend_from_slice(&delta[pos..pos + literals]);
+        pos += literals;
+    }
+    (0..len)
+        .map(|i| from.get(i).unwrap_or(&0) ^ xored.get(i).unwrap_or(&0))
+        .collect()
+}
+
+// LEB128
+fn put_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Chip8, Platform, Quirks, System};
+
+    #[test]
+    fn deltas() {
+        let long: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        let mut changed = long.clone();
+        changed[3] ^= 0xFF;
+        changed[500..700].fill(0xAA);
+        for (from, to) in [
+            (&[][..], &[][..]),
+            (&[1, 2, 3], &[1, 2, 3]),
+            (&[1, 2, 3], &[0, 2, 0, 4, 5]),
+            (&[1, 2, 3, 4, 5], &[1]),
+            (&long, &changed),
+            (&changed, &long[..300]),
+            (&[], &long),
+        ] {
+            assert_eq!(decode_delta(from, &encode_delta(from, to)), to);
+        }
+        // Runs of zeros longer than a byte only cost a few of them
+        assert!(encode_delta(&long, &changed).len() < 220);
+    }
+
+    #[test]
+    fn ring_wrap() {
+        // Snapshots of a running machine, RAM changing each time
+        let mut chip8 = Chip8::init(Platform::Chip8, Quirks::VIP, Some(0));
+        // V0 = random 0xFF, I = 0x300 + V1, V1 += 1, save V0, loop
+        chip8
+            .load_program(&[
+                0xC0, 0xFF, 0xA3, 0x00, 0xF1, 0x1E, 0x71, 0x01, 0xF0, 0x55, 0x12, 0x00,
+            ])
+            .unwrap();
+        let states: Vec<Vec<u8>> = (0..25)
+            .map(|_| {
+                chip8.run_frame(6).unwrap();
+                chip8.save_state()
+            })
+            .collect();
+
+        let mut rewind = Rewind::new(8);
+        for state in &states {
+            rewind.push(state.clone());
+        }
+        assert_eq!(rewind.len(), 8);
+        // Only the latest `depth` snapshots are kept, the last one being current
+        for state in states[17..24].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.len(), 1);
+
+        // Pushing after popping picks up from the rewound snapshot
+        rewind.push(states[0].clone());
+        assert_eq!(rewind.pop().as_ref(), Some(&states[17]));
+    }
+
+    #[test]
+    fn shallow_rings() {
+        let mut rewind = Rewind::new(0);
+        rewind.push(vec![1]);
+        assert!(rewind.is_empty());
+
+        let mut rewind = Rewind::new(1);
+        rewind.push(vec![1]);
+        rewind.push(vec![2]);
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.pop(), None);
+
+        let mut rewind = Rewind::new(3);
+        rewind.push(vec![1]);
+        rewind.push(vec![2, 3]);
+        rewind.clear();
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Result, anyhow};
+
+use crate::errors::SaveStateError;
+
+// Save states are a plain concatenation of big endian fields, each part of the machine writing
+// and reading back its own ones in the same order. Bump the version on any layout change.
+// Other files (e.g. input movies) use the same encoding, with their own magic and version.
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"RC8S";
+pub const SAVE_STATE_VERSION: u8 = 4;
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::with_magic(SAVE_STATE_MAGIC, SAVE_STATE_VERSION)
+    }
+
+    pub fn with_magic(magic: &[u8], version: u8) -> Self {
+        let mut writer = Self::default();
+        writer.put_bytes(magic);
+        writer.put_u8(version);
+        writer
+    }
+
+    pub fn put_u8(&mut self, val: u8) {
+        self.data.push(val);
+    }
+
+    pub fn put_bool(&mut self, val: bool) {
+        self.data.push(val as u8);
+    }
+
+    pub fn put_u16(&mut self, val: u16) {
+        self.data.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn put_u32(&mut self, val: u32) {
+        self.data.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, val: u64) {
+        self.data.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn put_u128(&mut self, val: u128) {
+        self.data.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn put_bytes(&mut self, val: &[u8]) {
+        self.data.extend_from_slice(val);
+    }
+
+    // Variable length byte array, prefixed by its length
+    pub fn put_blob(&mut self, val: &[u8]) {
+        self.put_u32(val.len() as u32);
+        self.put_bytes(val);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        Self::with_magic(data, SAVE_STATE_MAGIC, SAVE_STATE_VERSION)
+    }
+
+    pub fn with_magic(data: &'a [u8], magic: &[u8], version: u8) -> Result<Self> {
+        let mut reader = Self { data, pos: 0 };
+        if reader.get_bytes(magic.len())? != magic {
+            return Err(anyhow!(SaveStateError::new(format!(
+                "bad magic, not a {} file",
+                String::from_utf8_lossy(magic)
+            ))));
+        }
+        let file_version = reader.get_u8()?;
+        if file_version != version {
+            return Err(anyhow!(SaveStateError::new(format!(
+                "unsupported version {} (expected {})",
+                file_version, version
+            ))));
+        }
+        Ok(reader)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let Some(bytes) = self.data.get(self.pos..self.pos + len) else {
+            return Err(anyhow!(SaveStateError::new("truncated data")));
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn get_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.get_bytes(N)?.try_into().expect("slice of the requested length"))
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get_u8()? != 0)
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u128(&mut self) -> Result<u128> {
+        Ok(u128::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_blob(&mut self) -> Result<&'a [u8]> {
+        let len = self.get_u32()? as usize;
+        self.get_bytes(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Chip8, Platform, Quirks, Register, System};
+
+    // V0 = random 0xFF, loops forever
+    const RANDOM_LOOP: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    fn random_bytes(chip8: &mut Chip8, count: usize) -> Vec<u16> {
+        (0..count)
+            .map(|_| {
+                chip8.exec_instruction().unwrap();
+                chip8.exec_instruction().unwrap();
+                chip8.get_register(Register::V(0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.put_u8(0x12);
+        writer.put_bool(true);
+        writer.put_u16(0x3456);
+        writer.put_u32(0x789ABCDE);
+        writer.put_u64(u64::MAX - 1);
+        writer.put_u128(u128::MAX / 3);
+        writer.put_blob(b"blob");
+        writer.put_bytes(&[1, 2, 3]);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.get_u8().unwrap(), 0x12);
+        assert!(reader.get_bool().unwrap());
+        assert_eq!(reader.get_u16().unwrap(), 0x3456);
+        assert_eq!(reader.get_u32().unwrap(), 0x789ABCDE);
+        assert_eq!(reader.get_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(reader.get_u128().unwrap(), u128::MAX / 3);
+        assert_eq!(reader.get_blob().unwrap(), b"blob");
+        assert_eq!(reader.get_array().unwrap(), [1, 2, 3]);
+        assert!(reader.is_at_end());
+        assert!(reader.get_u8().is_err());
+    }
+
+    #[test]
+    fn bad_headers() {
+        let data = Chip8::init(Platform::Chip8, Quirks::VIP, Some(0)).save_state();
+        let reason = |data: &[u8]| StateReader::new(data).err().unwrap().to_string();
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert!(reason(&bad_magic).contains("bad magic"));
+        let mut bad_version = data.clone();
+        bad_version[SAVE_STATE_MAGIC.len()] = SAVE_STATE_VERSION - 1;
+        assert!(reason(&bad_version).contains(&format!(
+            "unsupported version {} (expected {})",
+            SAVE_STATE_VERSION - 1,
+            SAVE_STATE_VERSION
+        )));
+        assert!(reason(&data[..2]).contains("truncated data"));
+        assert!(StateReader::with_magic(&data, b"RC8M", SAVE_STATE_VERSION).is_err());
+    }
+
+    #[test]
+    fn truncated_states() {
+        let mut chip8 = Chip8::init(Platform::SChip, Quirks::SCHIP, Some(1));
+        chip8.load_program(&RANDOM_LOOP).unwrap();
+        random_bytes(&mut chip8, 1);
+        let state = chip8.save_state();
+        for len in 0..state.len() {
+            assert!(chip8.load_state(&state[..len]).is_err(), "{len} bytes");
+        }
+        // The machine is left as is
+        assert_eq!(chip8.save_state(), state);
+    }
+
+    #[test]
+    fn machine_round_trip() {
+        for platform in [Platform::Chip8, Platform::SChip, Platform::XoChip] {
+            let mut chip8 = Chip8::init(platform, Quirks::XOCHIP, Some(1234));
+            chip8.load_program(&RANDOM_LOOP).unwrap();
+            random_bytes(&mut chip8, 10);
+            let state = chip8.save_state();
+
+            // The seed and the RNG position come along with the rest of the machine
+            let mut restored = Chip8::init(Platform::Chip8, Quirks::VIP, Some(5678));
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.save_state(), state);
+            assert_eq!(restored.get_seed(), 1234);
+            assert_eq!(restored.get_platform(), platform);
+            assert_eq!(restored.get_quirks(), Quirks::XOCHIP);
+            assert_eq!(
+                random_bytes(&mut restored, 10),
+                random_bytes(&mut chip8, 10)
+            );
+        }
+    }
+}
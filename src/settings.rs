@@ -0,0 +1,130 @@
+use {
+    anyhow::{Result, anyhow},
+    rusty_chip8::{
+        errors::ConfigError,
+        systems::{Palette, Platform, Quirks},
+    },
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, io::ErrorKind, path::PathBuf},
+};
+
+use crate::{keymap::KeyMap, loader::config_dir};
+
+const DEFAULT_SCALE: u32 = 16;
+
+// How a program is run, unset fields falling back to the next source of settings, then to the
+// defaults of the platform
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    // Instructions executed per frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipf: Option<u32>,
+}
+
+impl MachineSettings {
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            platform: self.platform.or(fallback.platform),
+            quirks: self.quirks.or(fallback.quirks),
+            ipf: self.ipf.or(fallback.ipf),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn get_platform(&self) -> Platform {
+        self.platform.unwrap_or(Platform::Chip8)
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks.unwrap_or(self.get_platform().default_quirks())
+    }
+
+    pub fn get_cycles_per_frame(&self) -> u32 {
+        self.ipf
+            .unwrap_or(self.get_platform().default_cycles_per_frame())
+    }
+}
+
+// Settings file, in TOML, edited from the Preferences window
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // Size of a CHIP-8 pixel on screen, applied on the next start
+    pub scale: u32,
+    // From 0 to 1
+    pub volume: f32,
+    pub palette: Palette,
+    pub keys: KeyMap,
+    // Whether programs are set up as the CHIP-8 database says, colours included
+    pub identify: bool,
+    pub machine: MachineSettings,
+    // Overrides of `machine` for single programs, keyed by the SHA-1 of their file
+    pub roms: BTreeMap<String, MachineSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            scale: DEFAULT_SCALE,
+            volume: 1.0,
+            palette: Palette::default(),
+            keys: KeyMap::default(),
+            identify: true,
+            machine: MachineSettings::default(),
+            roms: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("settings.toml"))
+    }
+
+    // Settings missing from the file, or the file itself, are defaulted
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|err| anyhow!(ConfigError::new(format!("{} : {}", path.display(), err)))),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = Self::path() else {
+            return Err(anyhow!(ConfigError::new(
+                "no configuration directory, HOME isn't set"
+            )));
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(std::fs::write(path, toml::to_string(self)?)?)
+    }
+
+    // `overrides` (e.g. from the command line) take precedence over the settings of the program,
+    // then over the ones `identified` from the database, then over the global ones
+    pub fn machine_for(
+        &self,
+        program_sha1: &str,
+        overrides: MachineSettings,
+        identified: MachineSettings,
+    ) -> MachineSettings {
+        overrides
+            .or(self.roms.get(program_sha1).copied().unwrap_or_default())
+            .or(if self.identify { identified } else { MachineSettings::default() })
+            .or(self.machine)
+    }
+}
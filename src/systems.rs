@@ -1,29 +1,44 @@
 use {
-    anyhow::{Context, Result, anyhow},
-    rand::{Rng, SeedableRng, rngs::StdRng},
-    rodio::{Sink, Source, source::SineWave},
-    std::{
-        sync::{Arc, RwLock},
-        time::{Duration, Instant},
-    },
-    winit::keyboard::KeyCode,
-    winit_input_helper::WinitInputHelper,
+    anyhow::{Result, anyhow},
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaCha12Rng,
+    serde::{Deserialize, Serialize},
+    std::{borrow::Cow, fmt, ops::RangeInclusive},
 };
 
 use crate::{
     debug::Backtrace,
-    errors::{InvalidAccessError, InvalidInstructionError, ProgramLoadingError},
+    errors::{Chip8Fault, RegisterError, SaveStateError},
+    keypad::Keypad,
     mem::{Chip8Mem, Memory16Bit},
+    savestate::{StateReader, StateWriter},
 };
 
+// Emulation core, independent from any window, input or audio device: the frontend feeds it the
+// keypad state and polls the buzzer state and display, so that it can also run headless.
 pub trait System {
-    fn init() -> Self;
+    // A random seed is picked if none is given, see `get_seed` to replay the run
+    fn init(platform: Platform, quirks: Quirks, seed: Option<u64>) -> Self;
     fn load_program(&mut self, program_data: &[u8]) -> Result<()>;
-    fn exec_instruction(
-        &mut self,
-        input: Arc<RwLock<WinitInputHelper>>,
-        sink: Option<&Sink>,
-    ) -> Result<()>;
+    fn set_keypad(&mut self, keypad: Keypad);
+    fn get_buzzer_state(&self) -> BuzzerState;
+    // Executes a single instruction, never blocking
+    fn exec_instruction(&mut self) -> Result<()>;
+    // Ticks the timers, as done by the 60Hz vertical blank interrupt
+    fn vblank(&mut self);
+    // Executes up to `cycles_per_frame` instructions (less if the program waits for the vblank),
+    // then the vblank. Called 60 times per second, this sets the emulation speed.
+    fn run_frame(&mut self, cycles_per_frame: u32) -> Result<()>;
+}
+
+// What the frontend is expected to play
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuzzerState {
+    Off,
+    // The single tone of the original CHIP-8 buzzer
+    Tone,
+    // XO-CHIP 128 bit audio pattern, played in a loop at 4000*2^((pitch-64)/48) bits per second
+    Pattern { pattern: [u8; 0x10], pitch: u8 },
 }
 
 pub struct Chip8 {
@@ -34,22 +49,260 @@ pub struct Chip8 {
     delay: u8,
     sound: u8,
     ram: Chip8Mem,
-    rng: StdRng,
-    last_frame: Instant,
+    seed: u64,
+    rng: ChaCha12Rng,
+    // whether the vblank happened since the last draw
     draw_allowed: bool,
     pc_backtrace: Backtrace<u16>,
-    waitkey_state: (Option<KeyCode>, u8),
+    keypad: Keypad,
+    // keys pressed since the previous keypad update
+    new_keypresses: Keypad,
+    waitkey_state: (Option<u8>, u8),
+    platform: Platform,
+    quirks: Quirks,
+    rpl_flags: [u8; 0x10],
+    audio_pattern: [u8; 0x10],
+    pitch: u8,
+    halted: bool,
+}
+
+/// The instruction set and display the interpreter emulates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    /// Original COSMAC VIP CHIP-8
+    Chip8,
+    /// SUPER-CHIP 1.1, adding a 128x64 hi-res mode, scrolling, 16x16 sprites and a big font
+    SChip,
+    /// XO-CHIP, extending SCHIP with 64K of RAM, a second display plane and programmable audio
+    XoChip,
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Self::Chip8),
+            "schip" | "superchip" => Some(Self::SChip),
+            "xochip" | "xo-chip" => Some(Self::XoChip),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        [Self::Chip8, Self::SChip, Self::XoChip].into_iter().find(|platform| *platform as u8 == id)
+    }
+
+    // Rough speeds of the original interpreters, XO-CHIP not having any
+    pub fn default_cycles_per_frame(&self) -> u32 {
+        match self {
+            Self::Chip8 => 15,
+            Self::SChip => 30,
+            Self::XoChip => 100,
+        }
+    }
+
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Self::Chip8 => Quirks::VIP,
+            Self::SChip => Quirks::SCHIP,
+            Self::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    // XO-CHIP programs may span the whole 64K, so its stack is kept at the very end of RAM
+    pub fn stack_base(&self) -> u16 {
+        match self {
+            Self::Chip8 | Self::SChip => CHIP8_STACK_BASE_ADDR,
+            Self::XoChip => XOCHIP_STACK_BASE_ADDR,
+        }
+    }
+
+    // Values SP can take, from an empty stack to a full one
+    pub fn stack_range(&self) -> RangeInclusive<u16> {
+        self.stack_base()..=self.stack_base() + CHIP8_STACK_LEN
+    }
+}
+
+/// Registers visible to debuggers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "i" => Some(Self::I),
+            "pc" => Some(Self::Pc),
+            "sp" => Some(Self::Sp),
+            "dt" | "delay" => Some(Self::Delay),
+            "st" | "sound" => Some(Self::Sound),
+            reg => match reg.as_bytes() {
+                [b'v', digit] => (*digit as char).to_digit(16).map(|reg| Self::V(reg as u8)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V(reg) => write!(f, "v{reg:X}"),
+            Self::I => write!(f, "i"),
+            Self::Pc => write!(f, "pc"),
+            Self::Sp => write!(f, "sp"),
+            Self::Delay => write!(f, "dt"),
+            Self::Sound => write!(f, "st"),
+        }
+    }
+}
+
+/// Where FX55/FX65 leave I once the registers are stored/loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryIncrement {
+    /// after the last register, as the COSMAC VIP does
+    XPlusOne,
+    /// on the last register, as CHIP-48 does
+    X,
+    /// where it was, as SUPER-CHIP does
+    Unchanged,
+}
+
+impl MemoryIncrement {
+    pub const ALL: [Self; 3] = [Self::XPlusOne, Self::X, Self::Unchanged];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::XPlusOne => "I += X + 1",
+            Self::X => "I += X",
+            Self::Unchanged => "I unchanged",
+        }
+    }
+}
+
+/// Behaviours that differ between the historical CHIP-8 interpreters. The default is the original
+/// COSMAC VIP one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of copying VY into VX first
+    pub shift: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// FX55/FX65 increment I
+    pub memory_increment: MemoryIncrement,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_vx: bool,
+    /// DXYN waits for the next vblank before drawing
+    pub display_wait: bool,
+    /// sprites are clipped at the screen edges instead of wrapping around
+    pub clipping: bool,
+}
+
+impl Quirks {
+    pub const VIP: Self = Self {
+        shift: false,
+        vf_reset: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_vx: false,
+        display_wait: true,
+        clipping: true,
+    };
+    pub const CHIP48: Self = Self {
+        shift: true,
+        vf_reset: false,
+        memory_increment: MemoryIncrement::X,
+        jump_vx: true,
+        display_wait: false,
+        clipping: true,
+    };
+    pub const SCHIP: Self = Self {
+        shift: true,
+        vf_reset: false,
+        memory_increment: MemoryIncrement::Unchanged,
+        jump_vx: true,
+        display_wait: false,
+        clipping: true,
+    };
+    pub const XOCHIP: Self = Self {
+        shift: false,
+        vf_reset: false,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_vx: false,
+        display_wait: false,
+        clipping: false,
+    };
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Self::VIP),
+            "chip48" | "chip-48" => Some(Self::CHIP48),
+            "schip" | "superchip" => Some(Self::SCHIP),
+            "xochip" | "xo-chip" => Some(Self::XOCHIP),
+            _ => None,
+        }
+    }
+
+    // Bit 2 set for I += X + 1 and bit 6 for I += X, which older movies and states don't have
+    pub(crate) fn to_bits(self) -> u8 {
+        [
+            self.shift,
+            self.vf_reset,
+            self.memory_increment == MemoryIncrement::XPlusOne,
+            self.jump_vx,
+            self.display_wait,
+            self.clipping,
+            self.memory_increment == MemoryIncrement::X,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, quirk)| bits | (*quirk as u8) << i)
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        let quirk = |i: u8| bits & (1 << i) != 0;
+        Self {
+            shift: quirk(0),
+            vf_reset: quirk(1),
+            memory_increment: match (quirk(2), quirk(6)) {
+                (true, _) => MemoryIncrement::XPlusOne,
+                (false, true) => MemoryIncrement::X,
+                (false, false) => MemoryIncrement::Unchanged,
+            },
+            jump_vx: quirk(3),
+            display_wait: quirk(4),
+            clipping: quirk(5),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::VIP
+    }
 }
 
-const CHIP8_PC_START: u16 = 0x200;
-const CHIP8_MAX_PROG_SIZE: u16 = CHIP8_STACK_BASE_ADDR - CHIP8_PC_START;
+pub const CHIP8_PC_START: u16 = 0x200;
 pub const CHIP8_DISP_BUF_ADDR: u16 = 0xF00;
 pub const CHIP8_DISP_BUF_LEN: u16 = 0x100;
 pub const CHIP8_DISP_WIDTH: u16 = 64;
 pub const CHIP8_DISP_HEIGHT: u16 = 32;
+pub const SCHIP_DISP_WIDTH: u16 = 128;
+pub const SCHIP_DISP_HEIGHT: u16 = 64;
 pub const CHIP8_STACK_BASE_ADDR: u16 = 0xEA0;
+// SP is left pointing past the stack once it's full, so it has to end before 0xFFFF
+pub const XOCHIP_STACK_BASE_ADDR: u16 = 0xFF9E;
+pub const CHIP8_STACK_LEN: u16 = 0x60;
 pub const CHIP8_FONT_START: u16 = 0x50;
 pub const CHIP8_FONT_HEIGHT: u8 = 0x5;
+pub const SCHIP_BIG_FONT_START: u16 = CHIP8_FONT_START + CHIP8_FONT.len() as u16;
+pub const SCHIP_BIG_FONT_HEIGHT: u8 = 0xA;
 const CHIP8_FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -68,20 +321,65 @@ const CHIP8_FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
-const DISP_COLOR_BG: [u8; 4] = [0x22, 0x11, 0x10, 0xFF];
-const DISP_COLOR_FG: [u8; 4] = [0xFF, 0x99, 0x00, 0xFF];
+const SCHIP_BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+const DISP_COLOR_BG: [u8; 3] = [0x22, 0x11, 0x10];
+const DISP_COLOR_FG: [u8; 3] = [0xFF, 0x99, 0x00];
+const DISP_COLOR_FG2: [u8; 3] = [0x99, 0x33, 0x11];
+const DISP_COLOR_BLEND: [u8; 3] = [0xFF, 0xEE, 0xAA];
+const XOCHIP_DEFAULT_PITCH: u8 = 64;
+
+/// RGB colours the display is drawn with, by which of the 2 XO-CHIP planes a pixel is set on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+    pub plane2: [u8; 3],
+    pub both_planes: [u8; 3],
+}
 
-pub struct Chip8State {
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            background: DISP_COLOR_BG,
+            foreground: DISP_COLOR_FG,
+            plane2: DISP_COLOR_FG2,
+            both_planes: DISP_COLOR_BLEND,
+        }
+    }
+}
+
+// RAM is only borrowed, as it is way too big to be copied on each instruction for the backtrace
+// on XO-CHIP.
+pub struct Chip8State<'a> {
     pub i: u16,
     pub sp: u16,
     pub pc: u16,
     pub v: [u8; 0x10],
     pub delay: u8,
     pub sound: u8,
-    pub ram: Chip8Mem,
+    pub ram: Cow<'a, Chip8Mem>,
+    pub platform: Platform,
+    pub quirks: Quirks,
 }
-impl From<&Chip8> for Chip8State {
-    fn from(chip8: &Chip8) -> Self {
+impl<'a> From<&'a Chip8> for Chip8State<'a> {
+    fn from(chip8: &'a Chip8) -> Self {
         Self {
             i: chip8.i,
             sp: chip8.sp,
@@ -89,16 +387,124 @@ impl From<&Chip8> for Chip8State {
             v: chip8.v,
             delay: chip8.delay,
             sound: chip8.sound,
-            ram: chip8.ram.clone(),
+            ram: Cow::Borrowed(&chip8.ram),
+            platform: chip8.platform,
+            quirks: chip8.quirks,
         }
     }
 }
 
 impl Chip8 {
-    pub fn get_state(&self) -> Chip8State {
+    pub fn get_state(&self) -> Chip8State<'_> {
         self.into()
     }
 
+    // Snapshot of the whole machine, input and backtrace excepted
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_u8(self.platform as u8);
+        writer.put_u8(self.quirks.to_bits());
+        writer.put_u16(self.i);
+        writer.put_u16(self.sp);
+        writer.put_u16(self.pc);
+        writer.put_bytes(&self.v);
+        writer.put_u8(self.delay);
+        writer.put_u8(self.sound);
+        writer.put_u64(self.seed);
+        writer.put_bytes(&self.rng.get_seed());
+        writer.put_u64(self.rng.get_stream());
+        writer.put_u128(self.rng.get_word_pos());
+        writer.put_bool(self.draw_allowed);
+        writer.put_bool(self.waitkey_state.0.is_some());
+        writer.put_u8(self.waitkey_state.0.unwrap_or(0));
+        writer.put_u8(self.waitkey_state.1);
+        writer.put_bytes(&self.rpl_flags);
+        writer.put_bytes(&self.audio_pattern);
+        writer.put_u8(self.pitch);
+        writer.put_bool(self.halted);
+        self.ram.save_state(&mut writer);
+        writer.finish()
+    }
+
+    // Restores a snapshot made by `save_state`. The machine is left untouched on error.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(data)?;
+        let platform_id = reader.get_u8()?;
+        let Some(platform) = Platform::from_id(platform_id) else {
+            return Err(anyhow!(SaveStateError::new(format!(
+                "unknown platform {}",
+                platform_id
+            ))));
+        };
+        let quirks = Quirks::from_bits(reader.get_u8()?);
+        let i = reader.get_u16()?;
+        let sp = reader.get_u16()?;
+        let pc = reader.get_u16()?;
+        let v = reader.get_array()?;
+        let delay = reader.get_u8()?;
+        let sound = reader.get_u8()?;
+        let seed = reader.get_u64()?;
+        let mut rng = ChaCha12Rng::from_seed(reader.get_array()?);
+        rng.set_stream(reader.get_u64()?);
+        rng.set_word_pos(reader.get_u128()?);
+        let draw_allowed = reader.get_bool()?;
+        let waiting_key = reader.get_bool()?;
+        let key = reader.get_u8()?;
+        let waitkey_state = (waiting_key.then_some(key), reader.get_u8()?);
+        let rpl_flags = reader.get_array()?;
+        let audio_pattern = reader.get_array()?;
+        let pitch = reader.get_u8()?;
+        let halted = reader.get_bool()?;
+        let ram = Chip8Mem::load_state(&mut reader)?;
+        if ram.dump().len() != Chip8Mem::new(platform).dump().len() {
+            return Err(anyhow!(SaveStateError::new(
+                "memory size doesn't match the platform"
+            )));
+        }
+        if !platform.stack_range().contains(&sp) {
+            return Err(anyhow!(SaveStateError::new(format!(
+                "SP 0x{sp:X} out of the stack"
+            ))));
+        }
+
+        *self = Self {
+            i,
+            sp,
+            pc,
+            v,
+            delay,
+            sound,
+            ram,
+            seed,
+            rng,
+            draw_allowed,
+            pc_backtrace: Backtrace::new(20),
+            keypad: self.keypad,
+            new_keypresses: Keypad::default(),
+            waitkey_state,
+            platform,
+            quirks,
+            rpl_flags,
+            audio_pattern,
+            pitch,
+            halted,
+        };
+        Ok(())
+    }
+
+    pub fn get_platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Seed the CXNN random number generator was initialized with
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn get_mem(&self) -> &Chip8Mem {
         &self.ram
     }
@@ -107,225 +513,273 @@ impl Chip8 {
         &self.pc_backtrace
     }
 
-    pub fn set_pixels_frame(&self, frame: &mut [u8]) {
-        self.ram
-            .get(CHIP8_DISP_BUF_ADDR, CHIP8_DISP_BUF_LEN)
-            .expect("Chip8 display buffer badly defined")
-            .iter()
-            .enumerate()
-            .for_each(|(i, c)| {
-                frame[i * 32..(i + 1) * 32].copy_from_slice(
-                    [
-                        if c & 0b10000000 == 0b10000000 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b01000000 == 0b01000000 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00100000 == 0b00100000 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00010000 == 0b00010000 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00001000 == 0b00001000 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00000100 == 0b00000100 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00000010 == 0b00000010 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                        if c & 0b00000001 == 0b00000001 {
-                            DISP_COLOR_FG
-                        } else {
-                            DISP_COLOR_BG
-                        },
-                    ]
-                    .concat()
-                    .as_slice(),
-                );
-            });
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    fn is_key_pressed(input: Arc<RwLock<WinitInputHelper>>, key_byte: u8) -> bool {
-        let key = match key_byte {
-            0x0 => KeyCode::Numpad0,
-            0x1 => KeyCode::Numpad1,
-            0x2 => KeyCode::Numpad2,
-            0x3 => KeyCode::Numpad3,
-            0x4 => KeyCode::Numpad4,
-            0x5 => KeyCode::Numpad5,
-            0x6 => KeyCode::Numpad6,
-            0x7 => KeyCode::Numpad7,
-            0x8 => KeyCode::Numpad8,
-            0x9 => KeyCode::Numpad9,
-            0xA => KeyCode::NumpadDecimal,
-            0xB => KeyCode::NumpadEnter,
-            0xC => KeyCode::NumpadAdd,
-            0xD => KeyCode::NumpadSubtract,
-            0xE => KeyCode::NumpadMultiply,
-            0xF => KeyCode::NumpadDivide,
-            _ => unreachable!(),
-        };
-        return input.read().expect("Lock poisoned").key_held(key);
-    }
-
-    fn probe_keypress(input: Arc<RwLock<WinitInputHelper>>) -> Option<KeyCode> {
-        let keycodes = [
-            KeyCode::Numpad0,
-            KeyCode::Numpad1,
-            KeyCode::Numpad2,
-            KeyCode::Numpad3,
-            KeyCode::Numpad4,
-            KeyCode::Numpad5,
-            KeyCode::Numpad6,
-            KeyCode::Numpad7,
-            KeyCode::Numpad8,
-            KeyCode::Numpad9,
-            KeyCode::NumpadComma,
-            KeyCode::NumpadEnter,
-            KeyCode::NumpadAdd,
-            KeyCode::NumpadSubtract,
-            KeyCode::NumpadMultiply,
-            KeyCode::NumpadDivide,
-        ];
-
-        for cur_key in keycodes {
-            if let Ok(input) = input.read() && input.key_pressed(cur_key) {
-                return Some(cur_key);
+    pub fn get_register(&self, reg: Register) -> u16 {
+        match reg {
+            Register::V(reg) => self.v[reg as usize & 0xF] as u16,
+            Register::I => self.i,
+            Register::Pc => self.pc,
+            Register::Sp => self.sp,
+            Register::Delay => self.delay as u16,
+            Register::Sound => self.sound as u16,
+        }
+    }
+
+    // SP is only set within the stack, for calls and returns to keep working
+    pub fn set_register(&mut self, reg: Register, value: u16) -> Result<()> {
+        match reg {
+            Register::V(reg) => self.v[reg as usize & 0xF] = value as u8,
+            Register::I => self.i = value,
+            Register::Pc => self.pc = value & self.addr_mask(),
+            Register::Sp if !self.platform.stack_range().contains(&value) => {
+                let stack = self.platform.stack_range();
+                return Err(anyhow!(RegisterError::new(format!(
+                    "SP 0x{:X} out of the stack (0x{:X} to 0x{:X})",
+                    value,
+                    stack.start(),
+                    stack.end()
+                ))));
             }
+            Register::Sp => self.sp = value,
+            Register::Delay => self.delay = value as u8,
+            Register::Sound => self.sound = value as u8,
         }
+        Ok(())
+    }
+
+    // For debuggers to edit memory
+    pub fn get_mem_mut(&mut self) -> &mut Chip8Mem {
+        &mut self.ram
+    }
 
-        None
+    // Whether the next instruction can only be executed after the vblank
+    pub fn waits_vblank(&self) -> bool {
+        self.quirks.display_wait && !self.draw_allowed
+    }
+
+    // Addresses of the calls to the subroutines being executed, the innermost last
+    pub fn get_call_stack(&self) -> Vec<u16> {
+        let base = self.platform.stack_base();
+        let len = self.sp.saturating_sub(base);
+        self.ram
+            .get(base, len)
+            .map(|stack| {
+                stack
+                    .chunks_exact(2)
+                    .map(|addr| u16::from_be_bytes([addr[0], addr[1]]))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn disp_size(&self) -> (u16, u16) {
+        self.ram.disp_size()
+    }
+
+    // `frame` has to be disp_size() RGBA pixels big
+    pub fn set_pixels_frame(&self, frame: &mut [u8], palette: &Palette) {
+        let plane = |plane: usize| {
+            self.ram
+                .disp_buf(plane.min(self.ram.plane_count() - 1))
+                .iter()
+                .flat_map(|c| (0..8).map(move |bit| c & (0x80 >> bit) != 0))
+                .map(move |on| on && plane < self.ram.plane_count())
+        };
+        plane(0)
+            .zip(plane(1))
+            .zip(frame.chunks_exact_mut(4))
+            .for_each(|((on, on2), pixel)| {
+                let color = match (on, on2) {
+                    (false, false) => palette.background,
+                    (true, false) => palette.foreground,
+                    (false, true) => palette.plane2,
+                    (true, true) => palette.both_planes,
+                };
+                pixel[..3].copy_from_slice(&color);
+                pixel[3] = 0xFF;
+            });
+    }
+
+    fn addr_mask(&self) -> u16 {
+        (self.ram.dump().len() - 1) as u16
+    }
+
+    // 4 bytes for XO-CHIP's long I load, 2 for any other instruction
+    fn instruction_len(&self, addr: u16) -> u16 {
+        let long = self.platform == Platform::XoChip
+            && self.ram.get(addr & self.addr_mask(), 2).ok() == Some(&[0xF0, 0x00]);
+        if long { 4 } else { 2 }
+    }
+
+    // Skips the next instruction
+    fn skip_next(&mut self) {
+        let len = self.instruction_len(self.pc.wrapping_add(2));
+        self.pc = self.pc.wrapping_add(len) & self.addr_mask();
+    }
+
+    // Moves PC past the instruction it points to without executing it, e.g. to ignore a fault
+    pub fn skip_instruction(&mut self) {
+        self.pc = self.pc.wrapping_add(self.instruction_len(self.pc)) & self.addr_mask();
+    }
+
+    // Moves I as FX55/FX65 do once VX is stored/loaded
+    fn increment_i(&mut self, x: u8) {
+        let increment = match self.quirks.memory_increment {
+            MemoryIncrement::XPlusOne => x as u16 + 1,
+            MemoryIncrement::X => x as u16,
+            MemoryIncrement::Unchanged => return,
+        };
+        self.i = self.i.wrapping_add(increment) & self.addr_mask();
     }
 }
 
 impl System for Chip8 {
-    fn init() -> Self {
+    fn init(platform: Platform, quirks: Quirks, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
         Self {
             i: 0,
-            sp: CHIP8_STACK_BASE_ADDR,
+            sp: platform.stack_base(),
             pc: CHIP8_PC_START,
             v: [0; 0x10],
             delay: 0,
             sound: 0,
-            ram: Chip8Mem::new(),
-            rng: StdRng::from_os_rng(),
-            last_frame: Instant::now(),
+            ram: Chip8Mem::new(platform),
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
             draw_allowed: true,
             pc_backtrace: Backtrace::new(20),
+            keypad: Keypad::default(),
+            new_keypresses: Keypad::default(),
             waitkey_state: (None, 0),
+            platform,
+            quirks,
+            rpl_flags: [0; 0x10],
+            audio_pattern: [0; 0x10],
+            pitch: XOCHIP_DEFAULT_PITCH,
+            halted: false,
         }
     }
 
     fn load_program(&mut self, program_data: &[u8]) -> Result<()> {
-        if program_data.len() > CHIP8_MAX_PROG_SIZE as usize {
-            return Err(anyhow!(ProgramLoadingError::new(format!(
-                "Program too long, {} KB > 3,328 KB",
-                program_data.len()
-            ))));
+        let max_prog_size = self.platform.stack_base() - CHIP8_PC_START;
+        if program_data.len() > max_prog_size as usize {
+            return Err(anyhow!(Chip8Fault::RomTooLarge {
+                size: program_data.len(),
+                max: max_prog_size as usize,
+            }));
         }
         self.ram.set(CHIP8_FONT_START, &CHIP8_FONT)?;
+        self.ram.set(SCHIP_BIG_FONT_START, &SCHIP_BIG_FONT)?;
         self.ram.set(CHIP8_PC_START, program_data)
     }
 
-    fn exec_instruction(
-        &mut self,
-        input: Arc<RwLock<WinitInputHelper>>,
-        sink: Option<&Sink>,
-    ) -> Result<()> {
-        // Yes this is ugly, but it needs to be done w/ the current architecture because if we wait
-        // for the key to be released inside of the chip8 thread, it will hang the main thread and
-        // prevent it from updating inputs :) (+ we are emulating Cosmac VIP more than chip8 here
-        // (cf. https://www.laurencescotford.net/2020/07/19/chip-8-on-the-cosmac-vip-keyboard-input/))
-        if let Some(key) = self.waitkey_state.0 {
-            if input.read().expect("Lock poisoned").key_released(key) {
-                self.v[self.waitkey_state.1 as usize] = match key {
-                    KeyCode::Numpad0 => 0x0,
-                    KeyCode::Numpad1 => 0x1,
-                    KeyCode::Numpad2 => 0x2,
-                    KeyCode::Numpad3 => 0x3,
-                    KeyCode::Numpad4 => 0x4,
-                    KeyCode::Numpad5 => 0x5,
-                    KeyCode::Numpad6 => 0x6,
-                    KeyCode::Numpad7 => 0x7,
-                    KeyCode::Numpad8 => 0x8,
-                    KeyCode::Numpad9 => 0x9,
-                    KeyCode::NumpadComma => 0xA,
-                    KeyCode::NumpadEnter => 0xB,
-                    KeyCode::NumpadAdd => 0xC,
-                    KeyCode::NumpadSubtract => 0xD,
-                    KeyCode::NumpadMultiply => 0xE,
-                    KeyCode::NumpadDivide => 0xF,
-                    _ => unreachable!(),
-                };
+    fn set_keypad(&mut self, keypad: Keypad) {
+        self.new_keypresses = Keypad(keypad.0 & !self.keypad.0);
+        self.keypad = keypad;
+    }
+
+    fn get_buzzer_state(&self) -> BuzzerState {
+        if self.sound == 0 {
+            BuzzerState::Off
+        } else if self.platform == Platform::XoChip {
+            BuzzerState::Pattern {
+                pattern: self.audio_pattern,
+                pitch: self.pitch,
+            }
+        } else {
+            BuzzerState::Tone
+        }
+    }
+
+    fn exec_instruction(&mut self) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+        self.ram.clear_accesses();
 
+        // The key pressed on a WAITKEY is only taken into account once released, as on the Cosmac
+        // VIP (cf. https://www.laurencescotford.net/2020/07/19/chip-8-on-the-cosmac-vip-keyboard-input/)
+        if let Some(key) = self.waitkey_state.0 {
+            if !self.keypad.is_pressed(key) {
+                self.v[self.waitkey_state.1 as usize] = key;
                 self.waitkey_state = (None, 0);
             } else {
                 return Ok(());
             }
         }
 
-        // TODO: better timing ? waiting for vblank on draw (check details) ?
-        if self.last_frame.elapsed() >= Duration::from_nanos(16666666) {
-            self.last_frame = Instant::now();
-            self.draw_allowed = true;
-            self.sound = self.sound.saturating_sub(1);
-            self.delay = self.delay.saturating_sub(1);
-        }
-        let opcode = self
-            .ram
-            .get(self.pc, 2)
-            .map(|op| (op[0] >> 4, op[0] & 0x0F, op[1] >> 4, op[1] & 0x0F))
-            .unwrap_or_else(|_| panic!("Couldn't read opcode at 0x{:X}", self.pc));
+        let op = self.ram.get(self.pc, 2)?;
+        let (hi, lo) = (op[0], op[1]);
+        let opcode = (hi >> 4, hi & 0x0F, lo >> 4, lo & 0x0F);
+        let invalid_opcode = Chip8Fault::InvalidOpcode {
+            pc: self.pc,
+            opcode: u16::from_be_bytes([hi, lo]),
+        };
         match opcode {
-            // 0 - return subroutine (RTS) and display clear (CLS)
+            // 0 - return subroutine (RTS), display clear (CLS) and SCHIP display/system control
             (0x0, b, m, l) => {
+                let schip = self.platform != Platform::Chip8;
+                let xochip = self.platform == Platform::XoChip;
                 match (b, m, l) {
                     (0x0, 0xE, 0x0) => {
                         // CLS
-                        self.ram
-                            .set(CHIP8_DISP_BUF_ADDR, &[0; CHIP8_DISP_BUF_LEN as usize])?;
+                        self.ram.clear_disp();
+                    }
+
+                    (0x0, 0xC, n) if schip => {
+                        // SCRD
+                        self.ram.scroll_down(n);
+                    }
+
+                    (0x0, 0xD, n) if xochip => {
+                        // SCRU
+                        self.ram.scroll_up(n);
+                    }
+
+                    (0x0, 0xF, 0xB) if schip => {
+                        // SCRR
+                        self.ram.scroll_right(4);
+                    }
+
+                    (0x0, 0xF, 0xC) if schip => {
+                        // SCRL
+                        self.ram.scroll_left(4);
+                    }
+
+                    (0x0, 0xF, 0xD) if schip => {
+                        // EXIT
+                        self.halted = true;
+                        return Ok(());
+                    }
+
+                    (0x0, 0xF, 0xE) if schip => {
+                        // LORES
+                        self.ram.set_hires(false);
+                    }
+
+                    (0x0, 0xF, 0xF) if schip => {
+                        // HIRES
+                        self.ram.set_hires(true);
                     }
 
                     (0x0, 0xE, 0xE) => {
                         // RTS
-                        self.sp -= 2;
-                        if self.sp < CHIP8_STACK_BASE_ADDR {
-                            return Err(anyhow!(format!(
-                                "tried to return from main subroutine (SP decreased below {:X})",
-                                CHIP8_STACK_BASE_ADDR
-                            )));
-                        }
-                        let addr_bytes = self
-                            .ram
-                            .get(self.sp, 2)
-                            .context("while getting a return address")?;
+                        let Some(sp) = self
+                            .sp
+                            .checked_sub(2)
+                            .filter(|sp| *sp >= self.platform.stack_base())
+                        else {
+                            return Err(anyhow!(Chip8Fault::StackUnderflow));
+                        };
+                        let addr_bytes = self.ram.read(sp, 2)?;
+                        self.sp = sp;
                         self.pc = u16::from_be_bytes([addr_bytes[0], addr_bytes[1]]);
                     }
 
-                    err => {
-                        return Err(anyhow!(format!(
-                            "wrong operand 0x{:03X} for opcode 0x0 (should be 0x0E0 or 0x0EE)",
-                            u16::from_be_bytes([err.0, (err.1 << 4) + err.2])
-                        )));
-                    }
+                    _ => return Err(anyhow!(invalid_opcode)),
                 };
             }
 
@@ -337,10 +791,12 @@ impl System for Chip8 {
 
             // 2 - CALL
             (0x2, b, m, l) => {
-                if self.sp >= CHIP8_DISP_BUF_ADDR {
-                    return Err(anyhow!(InvalidInstructionError::new(
-                        "exceeded stack frame limit"
-                    )));
+                if self
+                    .sp
+                    .checked_sub(self.platform.stack_base())
+                    .is_none_or(|depth| depth >= CHIP8_STACK_LEN)
+                {
+                    return Err(anyhow!(Chip8Fault::StackOverflow { sp: self.sp }));
                 }
                 let _ = self.ram.set(self.sp, &self.pc.to_be_bytes());
                 self.sp += 2;
@@ -351,21 +807,38 @@ impl System for Chip8 {
             // 3 - SKIP.EQ direct
             (0x3, x, b, l) => {
                 if self.v[x as usize] == (b << 4) + l {
-                    self.pc = (self.pc + 2) & 0b0000111111111111;
+                    self.skip_next();
                 }
             }
 
             // 4 - SKIP.NE direct
             (0x4, x, b, l) => {
                 if self.v[x as usize] != (b << 4) + l {
-                    self.pc = (self.pc + 2) & 0b0000111111111111;
+                    self.skip_next();
                 }
             }
 
             // 5 - SKIP.EQ register
             (0x5, x, y, 0x0) => {
                 if self.v[x as usize] == self.v[y as usize] {
-                    self.pc = (self.pc + 2) & 0b0000111111111111;
+                    self.skip_next();
+                }
+            }
+
+            // 5 - XO-CHIP register range save/load (I unchanged)
+            (0x5, x, y, op @ (0x2 | 0x3)) if self.platform == Platform::XoChip => {
+                let regs: Vec<usize> = if x <= y {
+                    (x as usize..=y as usize).collect()
+                } else {
+                    (y as usize..=x as usize).rev().collect()
+                };
+                for (offset, reg) in regs.into_iter().enumerate() {
+                    let addr = self.i.wrapping_add(offset as u16);
+                    if op == 0x2 {
+                        self.ram.set_byte(addr, self.v[reg])?;
+                    } else {
+                        self.v[reg] = self.ram.read(addr, 1)?[0];
+                    }
                 }
             }
 
@@ -384,17 +857,23 @@ impl System for Chip8 {
                     0x1 => {
                         // OR
                         self.v[x as usize] |= self.v[y as usize];
-                        self.v[0xF] = 0x00;
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0x00;
+                        }
                     }
                     0x2 => {
                         // AND
                         self.v[x as usize] &= self.v[y as usize];
-                        self.v[0xF] = 0x00;
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0x00;
+                        }
                     }
                     0x3 => {
                         // XOR
                         self.v[x as usize] ^= self.v[y as usize];
-                        self.v[0xF] = 0x00;
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0x00;
+                        }
                     }
                     0x4 => {
                         // ADD
@@ -410,8 +889,9 @@ impl System for Chip8 {
                     }
                     0x6 => {
                         // SHR
-                        // TODO: let this be configurable
-                        self.v[x as usize] = self.v[y as usize];
+                        if !self.quirks.shift {
+                            self.v[x as usize] = self.v[y as usize];
+                        }
                         let carry = self.v[x as usize] & 0x01;
                         self.v[x as usize] >>= 1;
                         self.v[0xF] = carry;
@@ -424,26 +904,22 @@ impl System for Chip8 {
                     }
                     0xE => {
                         // SHL
-                        // TODO: let this be configurable
-                        self.v[x as usize] = self.v[y as usize];
+                        if !self.quirks.shift {
+                            self.v[x as usize] = self.v[y as usize];
+                        }
                         let carry = (self.v[x as usize] & 0x80) >> 7;
                         self.v[x as usize] <<= 1;
                         self.v[0xF] = carry;
                     }
 
-                    err => {
-                        return Err(anyhow!(InvalidInstructionError::new(format!(
-                            "wrong operation 0x{:X} for opcode 0x8 (should be 0x0 -> 0x7 or 0xE)",
-                            err
-                        ))));
-                    }
+                    _ => return Err(anyhow!(invalid_opcode)),
                 }
             }
 
             // 9 - SKIP.NE register
             (0x9, x, y, 0x0) => {
                 if self.v[x as usize] != self.v[y as usize] {
-                    self.pc = (self.pc + 2) & 0b0000111111111111;
+                    self.skip_next();
                 }
             }
 
@@ -452,44 +928,36 @@ impl System for Chip8 {
 
             // B - JMP relative
             (0xB, b, m, l) => {
+                let offset = if self.quirks.jump_vx { self.v[b as usize] } else { self.v[0] };
                 self.pc =
-                    (self.v[0] as u16 + u16::from_be_bytes([b, (m << 4) + l])) & 0b0000111111111111;
+                    (offset as u16 + u16::from_be_bytes([b, (m << 4) + l])) & self.addr_mask();
                 return Ok(());
             }
 
             // C - RAND (VX = rand() & BL)
             (0xC, x, b, l) => self.v[x as usize] = self.rng.random_range(0..=255) & ((b << 4) + l),
 
-            // D - DISP (draws sprite @ coord VX,VY, N pixels high)
+            // D - DISP (draws sprite @ coord VX,VY, N pixels high, or 16x16 for N = 0 on SCHIP)
             (0xD, x, y, n) => {
-                if n > 0xf {
-                    unreachable!(
-                        "Trying to draw a sprite with height {} > 0xf. This _should_ be impossible.",
-                        n
-                    );
-                }
-                if self.draw_allowed {
+                if self.draw_allowed || !self.quirks.display_wait {
                     self.draw_allowed = false;
-                    let sprite = self
-                        .ram
-                        .get(self.i, n as u16)
-                        .context("while fetching a sprite")?
-                        .to_owned();
+                    let wide = n == 0 && self.platform != Platform::Chip8;
+                    let sprite_len = self.ram.sprite_len(wide, n);
+                    let sprite = self.ram.read(self.i, sprite_len)?.to_owned();
                     // TODO: maybe directly take and pass address rather than sprite to load_sprite
                     self.v[0xF] = match Chip8Mem::load_sprite(
                         &mut self.ram,
                         &sprite,
+                        wide,
                         self.v[x as usize],
                         self.v[y as usize],
-                        n,
+                        self.quirks.clipping,
                     ) {
                         Ok(flag) => flag as u8,
                         Err(err) => return Err(err),
                     }
                 } else {
-                    std::thread::sleep(
-                        Duration::from_nanos(16666666).saturating_sub(self.last_frame.elapsed()),
-                    );
+                    // wait for the next frame
                     self.pc -= 2;
                 }
             }
@@ -497,30 +965,39 @@ impl System for Chip8 {
             // E - INPT checking
             (0xE, b, m, l) => match (b, m, l) {
                 (x, 0x9, 0xE) => {
-                    if Self::is_key_pressed(input, self.v[x as usize]) {
-                        self.pc += 2;
+                    if self.keypad.is_pressed(self.v[x as usize]) {
+                        self.skip_next();
                     }
                 }
                 (x, 0xA, 0x1) => {
-                    if !Self::is_key_pressed(input, self.v[x as usize]) {
-                        self.pc += 2;
+                    if !self.keypad.is_pressed(self.v[x as usize]) {
+                        self.skip_next();
                     }
                 }
-                err => {
-                    return Err(anyhow!(InvalidInstructionError::new(format!(
-                        "wrong operand 0x{:03X} for opcode 0xE (should be 0xE[X]9E or 0xE[X]A1)",
-                        u16::from_be_bytes([err.0, (err.1 << 4) + err.2])
-                    ))));
-                }
+                _ => return Err(anyhow!(invalid_opcode)),
             },
 
             // F - MISC things
             (0xF, x, op_b, op_l) => {
+                let xochip = self.platform == Platform::XoChip;
                 match (x, (op_b << 4) + op_l) {
+                    (0x0, 0x00) if xochip => {
+                        // LSETI (long I load from the next 2 bytes)
+                        let addr = self.ram.get(self.pc.wrapping_add(2) & self.addr_mask(), 2)?;
+                        self.i = u16::from_be_bytes([addr[0], addr[1]]);
+                        self.pc = self.pc.wrapping_add(2) & self.addr_mask();
+                    }
+                    (n, 0x01) if xochip && n < 4 => self.ram.select_planes(n), // PLANE
+                    (0x0, 0x02) if xochip => {
+                        // AUDIO
+                        let pattern = self.ram.read(self.i, 0x10)?;
+                        self.audio_pattern.copy_from_slice(pattern);
+                    }
                     (x, 0x07) => self.v[x as usize] = self.delay, // MOVD
                     (x, 0x0A) => {
                         // WAITKEY
-                        if let Some(key) = Self::probe_keypress(input.clone()) {
+                        if let Some(key) = self.new_keypresses.first_pressed() {
+                            self.new_keypresses = Keypad::default();
                             self.waitkey_state = (Some(key), x);
                         } else {
                             self.pc -= 2;
@@ -530,16 +1007,10 @@ impl System for Chip8 {
                     (x, 0x18) => {
                         // RMOVS
                         self.sound = self.v[x as usize];
-                        if let Some(sink) = sink {
-                            let source = SineWave::new(440.0) // TODO: custom square wave ?
-                                .take_duration(Duration::from_secs_f64(0.016666666 * self.sound as f64))
-                                .amplify(0.20);
-                            sink.append(source);
-                        }
                     }
                     (x, 0x1E) => {
                         // ADDI
-                        self.i = (self.i + self.v[x as usize] as u16) & 0b0000111111111111;
+                        self.i = self.i.wrapping_add(self.v[x as usize] as u16) & self.addr_mask();
                         // self.v[0xF] = 1; // if u12 overflows (on amiga at least)
                     }
                     (x, 0x29) => {
@@ -547,6 +1018,12 @@ impl System for Chip8 {
                         let offset = (self.v[x as usize] & 0x0F) as u16;
                         self.i = CHIP8_FONT_START + CHIP8_FONT_HEIGHT as u16 * offset;
                     }
+                    (x, 0x30) if self.platform != Platform::Chip8 => {
+                        // LOADBFNT
+                        let offset = (self.v[x as usize] & 0x0F) as u16;
+                        self.i = SCHIP_BIG_FONT_START + SCHIP_BIG_FONT_HEIGHT as u16 * offset;
+                    }
+                    (x, 0x3A) if xochip => self.pitch = self.v[x as usize], // PITCH
                     (x, 0x33) => {
                         // DCB
                         let byte = self.v[x as usize];
@@ -554,22 +1031,22 @@ impl System for Chip8 {
                             Ok(()) => (),
                             Err(err) => return Err(err),
                         };
-                        match self.ram.set_byte(self.i + 1, (byte % 100) / 10) {
+                        match self.ram.set_byte(self.i.wrapping_add(1), (byte % 100) / 10) {
                             Ok(()) => (),
                             Err(err) => return Err(err),
                         };
-                        match self.ram.set_byte(self.i + 2, byte % 10) {
+                        match self.ram.set_byte(self.i.wrapping_add(2), byte % 10) {
                             Ok(()) => (),
                             Err(err) => return Err(err),
                         };
                     }
                     (n, 0x55) => {
                         // STORE
-                        if self.i + (n as u16 & 0x0F) > 0xFFF {
-                            return Err(anyhow!(InvalidAccessError::new(format!(
-                                "Cannot STORE {:X} bytes of data at 0x{:03X}",
-                                n, self.i
-                            ))));
+                        if self.i as usize + (n as usize & 0x0F) > self.addr_mask() as usize {
+                            return Err(anyhow!(Chip8Fault::OutOfBounds {
+                                addr: self.i,
+                                len: n as u16 + 1,
+                            }));
                         }
                         for i in 0..=n {
                             match self.ram.set_byte(self.i + i as u16, self.v[i as usize]) {
@@ -577,38 +1054,184 @@ impl System for Chip8 {
                                 Err(err) => return Err(err),
                             }
                         }
-                        self.i = (self.i + n as u16 + 1) & 0b0000111111111111;
+                        self.increment_i(n);
                     }
                     (n, 0x65) => {
                         // LOAD
-                        if self.i + (n as u16 & 0x0F) > 0xFFF {
-                            return Err(anyhow!(InvalidAccessError::new(format!(
-                                "Cannot LOAD {:X} bytes of data from 0x{:03X}",
-                                n, self.i
-                            ))));
+                        if self.i as usize + (n as usize & 0x0F) > self.addr_mask() as usize {
+                            return Err(anyhow!(Chip8Fault::OutOfBounds {
+                                addr: self.i,
+                                len: n as u16 + 1,
+                            }));
                         }
-                        let regs = self.ram.get(self.i, n as u16 + 1)?;
+                        let regs = self.ram.read(self.i, n as u16 + 1)?;
                         self.v[..=n as usize].copy_from_slice(&regs[..=n as usize]);
-                        self.i = (self.i + n as u16 + 1) & 0b0000111111111111;
+                        self.increment_i(n);
+                    }
+                    (n, 0x75) if xochip || (self.platform == Platform::SChip && n < 8) => {
+                        // SAVEFLAGS
+                        self.rpl_flags[..=n as usize].copy_from_slice(&self.v[..=n as usize]);
                     }
-                    (x, b) => {
-                        return Err(anyhow!(InvalidInstructionError::new(format!(
-                            "Wrong operand 0x{:03X} for opcode 0xF",
-                            u16::from_be_bytes([x, b])
-                        ))));
+                    (n, 0x85) if xochip || (self.platform == Platform::SChip && n < 8) => {
+                        // LOADFLAGS
+                        self.v[..=n as usize].copy_from_slice(&self.rpl_flags[..=n as usize]);
                     }
+                    _ => return Err(anyhow!(invalid_opcode)),
                 }
             }
 
-            err => {
-                return Err(anyhow!(InvalidInstructionError::new(format!(
-                    "invalid opcode 0x{:X}",
-                    u16::from_be_bytes([(err.0 << 4) + err.1, (err.2 << 4) + err.3])
-                ))));
-            }
+            _ => return Err(anyhow!(invalid_opcode)),
+        };
+        let state = Chip8State {
+            i: self.i,
+            sp: self.sp,
+            pc: self.pc,
+            v: self.v,
+            delay: self.delay,
+            sound: self.sound,
+            ram: Cow::Borrowed(&self.ram),
+            platform: self.platform,
+            quirks: self.quirks,
         };
-        self.pc_backtrace.refresh(self.pc, self.get_state(), opcode);
-        self.pc = (self.pc + 2) & 0b0000111111111111;
+        self.pc_backtrace.refresh(self.pc, state, opcode);
+        self.pc = self.pc.wrapping_add(2) & self.addr_mask();
         Ok(())
     }
+
+    fn vblank(&mut self) {
+        self.ram.end_frame();
+        self.draw_allowed = true;
+        self.sound = self.sound.saturating_sub(1);
+        self.delay = self.delay.saturating_sub(1);
+    }
+
+    fn run_frame(&mut self, cycles_per_frame: u32) -> Result<()> {
+        for _ in 0..cycles_per_frame {
+            self.exec_instruction()?;
+            if self.waits_vblank() {
+                break;
+            }
+        }
+        self.vblank();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_increment() {
+        for (quirks, i) in [
+            (Quirks::VIP, 0x304),
+            (Quirks::CHIP48, 0x303),
+            (Quirks::SCHIP, 0x300),
+        ] {
+            // I = 0x300, then stores and loads V0 to V3 back
+            let mut chip8 = Chip8::init(Platform::Chip8, quirks, Some(0));
+            chip8
+                .load_program(&[0xA3, 0x00, 0xF3, 0x55, 0xF3, 0x65])
+                .unwrap();
+            chip8.exec_instruction().unwrap();
+            chip8.exec_instruction().unwrap();
+            assert_eq!(chip8.get_register(Register::I), i);
+            chip8.exec_instruction().unwrap();
+            assert_eq!(chip8.get_register(Register::I), 2 * i - 0x300);
+        }
+    }
+
+    #[test]
+    fn add_i_wraps_around() {
+        // I = 0xFFFF, V0 = 1, I += V0
+        let mut chip8 = Chip8::init(Platform::XoChip, Quirks::XOCHIP, Some(0));
+        chip8
+            .load_program(&[0xF0, 0x00, 0xFF, 0xFF, 0x60, 0x01, 0xF0, 0x1E])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.exec_instruction().unwrap();
+        }
+        assert_eq!(chip8.get_register(Register::I), 0);
+    }
+
+    #[test]
+    fn stack_faults() {
+        // Returns from nowhere
+        let mut chip8 = Chip8::init(Platform::Chip8, Quirks::VIP, Some(0));
+        chip8.load_program(&[0x00, 0xEE]).unwrap();
+        let err = chip8.exec_instruction().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Chip8Fault::StackUnderflow));
+        assert_eq!(chip8.get_register(Register::Sp), CHIP8_STACK_BASE_ADDR);
+
+        // Recurses until the stack at the very end of the XO-CHIP RAM is full
+        let mut chip8 = Chip8::init(Platform::XoChip, Quirks::XOCHIP, Some(0));
+        chip8.load_program(&[0x22, 0x00]).unwrap();
+        for _ in 0..CHIP8_STACK_LEN / 2 {
+            chip8.exec_instruction().unwrap();
+        }
+        let sp = XOCHIP_STACK_BASE_ADDR + CHIP8_STACK_LEN;
+        let err = chip8.exec_instruction().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Chip8Fault::StackOverflow { sp }));
+        assert_eq!(chip8.get_call_stack().len(), CHIP8_STACK_LEN as usize / 2);
+    }
+
+    #[test]
+    fn stack_pointer_bounds() {
+        let mut chip8 = Chip8::init(Platform::Chip8, Quirks::VIP, Some(0));
+        assert!(chip8.set_register(Register::Sp, 0).is_err());
+        assert!(chip8.set_register(Register::Sp, 0xF02).is_err());
+        chip8.set_register(Register::Sp, 0xF00).unwrap();
+        assert_eq!(chip8.get_register(Register::Sp), 0xF00);
+
+        // States with SP out of the stack are rejected, the machine being left as is
+        let mut state = chip8.save_state();
+        state[9..11].copy_from_slice(&0xE9Eu16.to_be_bytes());
+        assert!(chip8.load_state(&state).is_err());
+        assert_eq!(chip8.get_register(Register::Sp), 0xF00);
+        state[9..11].copy_from_slice(&0xEA2u16.to_be_bytes());
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.get_register(Register::Sp), 0xEA2);
+    }
+
+    #[test]
+    fn seeded_random() {
+        // V0 = random 0xFF, V1 = random 0x0F, loops forever
+        let random = |seed| {
+            let mut chip8 = Chip8::init(Platform::Chip8, Quirks::VIP, seed);
+            chip8
+                .load_program(&[0xC0, 0xFF, 0xC1, 0x0F, 0x12, 0x00])
+                .unwrap();
+            let values: Vec<_> = (0..32)
+                .flat_map(|_| {
+                    for _ in 0..3 {
+                        chip8.exec_instruction().unwrap();
+                    }
+                    [Register::V(0), Register::V(1)].map(|reg| chip8.get_register(reg))
+                })
+                .collect();
+            (chip8.get_seed(), values)
+        };
+
+        let (seed, values) = random(Some(42));
+        assert_eq!(seed, 42);
+        assert_eq!(random(Some(42)).1, values);
+        assert_ne!(random(Some(43)).1, values);
+        assert!(values.chunks(2).all(|vals| vals[1] <= 0x0F));
+
+        // Runs with a random seed are replayed from the one picked
+        let (seed, values) = random(None);
+        assert_eq!(random(Some(seed)).1, values);
+    }
+
+    #[test]
+    fn quirks_bits() {
+        for quirks in [Quirks::VIP, Quirks::CHIP48, Quirks::SCHIP, Quirks::XOCHIP] {
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks);
+        }
+        // Movies and states from before I += X was told apart, when CHIP-48 left I unchanged
+        assert_eq!(
+            Quirks::from_bits(Quirks::CHIP48.to_bits() & 0x3F),
+            Quirks::SCHIP
+        );
+    }
 }
@@ -0,0 +1,204 @@
+use {
+    anyhow::{Result, anyhow},
+    std::fmt,
+};
+
+use crate::{
+    disas::sprite_line,
+    errors::TestRomError,
+    systems::{Chip8, System},
+};
+
+// Test programs (e.g. Timendus' CHIP-8 test suite) report their results on the display : they
+// are run headless for a number of frames, then their display is compared with the image it
+// showed on a known good run, stored as a PBM file.
+
+/// Snapshot of the display, a pixel being set when it is on any of the planes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    pixels: Vec<bool>,
+}
+
+impl Framebuffer {
+    pub fn capture(chip8: &Chip8) -> Self {
+        let mem = chip8.get_mem();
+        let (width, height) = mem.disp_size();
+        let pixels = (0..mem.plane_count())
+            .map(|plane| mem.disp_buf(plane))
+            .fold(
+                vec![0u8; width as usize * height as usize / 8],
+                |acc, plane| {
+                    acc.iter()
+                        .zip(plane)
+                        .map(|(acc, byte)| acc | byte)
+                        .collect()
+                },
+            )
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0))
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn get_size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub fn get_pixel(&self, x: u16, y: u16) -> bool {
+        x < self.width
+            && y < self.height
+            && self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    // Pixels differing from `other`, all of them when their sizes differ
+    pub fn diff(&self, other: &Self) -> usize {
+        if self.get_size() != other.get_size() {
+            return self.pixels.len().max(other.pixels.len());
+        }
+        self.pixels
+            .iter()
+            .zip(&other.pixels)
+            .filter(|(pixel, other)| pixel != other)
+            .count()
+    }
+
+    // Plain PBM (P1), a line of text per row, for expected images to be reviewable in diffs
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width as usize) {
+            pbm.extend(row.iter().map(|on| if *on { '1' } else { '0' }));
+            pbm.push('\n');
+        }
+        pbm.into_bytes()
+    }
+
+    // Reads plain (P1) and raw (P4) PBM images
+    pub fn parse_pbm(data: &[u8]) -> Result<Self> {
+        let err = |reason: &str| anyhow!(TestRomError::new(format!("invalid PBM image, {reason}")));
+        // Header fields are separated by whitespace, `#` starting comments until the end of line
+        let mut pos = 0;
+        let mut field = || -> Option<&[u8]> {
+            loop {
+                match data.get(pos)? {
+                    b'#' => pos += data[pos..].iter().position(|c| *c == b'\n')?,
+                    c if c.is_ascii_whitespace() => pos += 1,
+                    _ => break,
+                }
+            }
+            let start = pos;
+            pos += data[pos..]
+                .iter()
+                .position(|c| c.is_ascii_whitespace())
+                .unwrap_or(data.len() - pos);
+            Some(&data[start..pos])
+        };
+        let magic = field().ok_or_else(|| err("missing header"))?.to_vec();
+        let mut dimension = || {
+            field()
+                .and_then(|field| std::str::from_utf8(field).ok()?.parse::<u16>().ok())
+                .filter(|dimension| *dimension > 0)
+                .ok_or_else(|| err("invalid size"))
+        };
+        let (width, height) = (dimension()?, dimension()?);
+        let len = width as usize * height as usize;
+        let pixels: Vec<bool> = match magic.as_slice() {
+            b"P1" => data[pos..]
+                .iter()
+                .filter(|c| !c.is_ascii_whitespace())
+                .map(|c| match c {
+                    b'0' => Ok(false),
+                    b'1' => Ok(true),
+                    _ => Err(err("invalid pixel")),
+                })
+                .take(len)
+                .collect::<Result<_>>()?,
+            // A single whitespace separates the header from the data, and rows are padded to bytes
+            b"P4" => data
+                .get(pos + 1..)
+                .unwrap_or_default()
+                .chunks((width as usize).div_ceil(8))
+                .take(height as usize)
+                .flat_map(|row| {
+                    (0..width as usize).map(move |x| {
+                        row.get(x / 8)
+                            .is_some_and(|byte| byte & (0x80 >> (x % 8)) != 0)
+                    })
+                })
+                .collect(),
+            _ => return Err(err("not a PBM image")),
+        };
+        if pixels.len() != len {
+            return Err(err("truncated data"));
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.pixels.chunks(self.width as usize) {
+            let line: String = row
+                .chunks(8)
+                .map(|byte| sprite_line(byte.iter().fold(0, |acc, on| acc << 1 | *on as u8)))
+                .collect();
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+// Runs the program for `frames` frames without any key pressed, or until it exits
+pub fn run_headless(chip8: &mut Chip8, frames: u32, cycles_per_frame: u32) -> Result<()> {
+    for _ in 0..frames {
+        if chip8.is_halted() {
+            break;
+        }
+        chip8.run_frame(cycles_per_frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reason the image is rejected for
+    fn parse_err(data: &[u8]) -> String {
+        let err = Framebuffer::parse_pbm(data).unwrap_err().to_string();
+        err.strip_prefix("TestRomError : invalid PBM image, ")
+            .unwrap_or(&err)
+            .to_string()
+    }
+
+    #[test]
+    fn parse_pbm() {
+        let plain = Framebuffer::parse_pbm(b"P1\n# 3x2\n3 2\n101\n0 1 1\n").unwrap();
+        assert_eq!(plain.get_size(), (3, 2));
+        assert_eq!(plain.pixels, [true, false, true, false, true, true]);
+        assert_eq!(Framebuffer::parse_pbm(&plain.to_pbm()).unwrap(), plain);
+        // Rows padded to bytes
+        let raw = Framebuffer::parse_pbm(b"P4 3 2\n\xA0\x60").unwrap();
+        assert_eq!(raw, plain);
+    }
+
+    #[test]
+    fn invalid_pbm() {
+        assert_eq!(parse_err(b"P1\n0 2\n"), "invalid size");
+        assert_eq!(parse_err(b"P4\n3 0\n"), "invalid size");
+        assert_eq!(parse_err(b"P1\n3\n"), "invalid size");
+        assert_eq!(parse_err(b"P1\n3 2\n101\n01"), "truncated data");
+        assert_eq!(parse_err(b"P1\n3 2\n101\n012"), "invalid pixel");
+        assert_eq!(parse_err(b"P2\n3 2\n"), "not a PBM image");
+        assert_eq!(parse_err(b""), "missing header");
+    }
+}
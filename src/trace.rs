@@ -0,0 +1,328 @@
+use {
+    anyhow::Result,
+    std::{fmt, io::Write, ops::RangeInclusive},
+};
+
+use crate::{
+    disas::disas_instruction,
+    mem::Memory16Bit,
+    savestate::{StateReader, StateWriter},
+    systems::Chip8,
+};
+
+// Execution traces list every instruction executed along with the registers it left, for runs to
+// be diffed between emulator versions or against other emulators. Text traces have a line per
+// instruction, binary ones are a header followed by big endian records, in which V registers only
+// appear when the instruction changed them.
+const TRACE_MAGIC: &[u8; 4] = b"RC8T";
+const TRACE_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "binary" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    // Instructions executed since the trace started, this one excluded
+    pub cycle: u64,
+    pub frame: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    // V registers the instruction changed, with their new value
+    pub v_changes: Vec<(u8, u8)>,
+    pub i: u16,
+    pub sp: u16,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl TraceRecord {
+    fn write(&self, writer: &mut StateWriter) {
+        writer.put_u64(self.cycle);
+        writer.put_u64(self.frame);
+        writer.put_u16(self.pc);
+        writer.put_u16(self.opcode);
+        writer.put_u16(self.i);
+        writer.put_u16(self.sp);
+        writer.put_u8(self.delay);
+        writer.put_u8(self.sound);
+        let mask = self
+            .v_changes
+            .iter()
+            .fold(0u16, |mask, (reg, _)| mask | 1 << reg);
+        writer.put_u16(mask);
+        for (_, value) in &self.v_changes {
+            writer.put_u8(*value);
+        }
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Self> {
+        let cycle = reader.get_u64()?;
+        let frame = reader.get_u64()?;
+        let pc = reader.get_u16()?;
+        let opcode = reader.get_u16()?;
+        let i = reader.get_u16()?;
+        let sp = reader.get_u16()?;
+        let delay = reader.get_u8()?;
+        let sound = reader.get_u8()?;
+        let mask = reader.get_u16()?;
+        let mut v_changes = Vec::new();
+        for reg in (0..16).filter(|reg| mask & 1 << reg != 0) {
+            v_changes.push((reg, reader.get_u8()?));
+        }
+        Ok(Self {
+            cycle,
+            frame,
+            pc,
+            opcode,
+            v_changes,
+            i,
+            sp,
+            delay,
+            sound,
+        })
+    }
+}
+
+// The line of text traces, binary ones being printed the same way
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nibbles = (
+            (self.opcode >> 12) as u8,
+            (self.opcode >> 8 & 0xF) as u8,
+            (self.opcode >> 4 & 0xF) as u8,
+            (self.opcode & 0xF) as u8,
+        );
+        write!(
+            f,
+            "{:>8} {:>6} {:03x}: {:04x}  {:<20} i={:03x} sp={:03x} dt={:02x} st={:02x}",
+            self.cycle,
+            self.frame,
+            self.pc,
+            self.opcode,
+            disas_instruction(nibbles, None),
+            self.i,
+            self.sp,
+            self.delay,
+            self.sound
+        )?;
+        for (reg, value) in &self.v_changes {
+            write!(f, " v{reg:X}={value:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+// Writes the instructions the debugger executes, between `start_instruction` and `end_instruction`
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    // Only instructions at these addresses, and executed during these frames, are written
+    addr_filter: Option<RangeInclusive<u16>>,
+    frame_filter: Option<RangeInclusive<u64>>,
+    cycle: u64,
+    frame: u64,
+    // State before the instruction being executed
+    pc: u16,
+    opcode: u16,
+    v: [u8; 0x10],
+}
+
+impl Tracer {
+    pub fn new(mut out: impl Write + Send + 'static, format: TraceFormat) -> Result<Self> {
+        if format == TraceFormat::Binary {
+            out.write_all(&StateWriter::with_magic(TRACE_MAGIC, TRACE_VERSION).finish())?;
+        }
+        Ok(Self {
+            out: Box::new(out),
+            format,
+            addr_filter: None,
+            frame_filter: None,
+            cycle: 0,
+            frame: 0,
+            pc: 0,
+            opcode: 0,
+            v: [0; 0x10],
+        })
+    }
+
+    pub fn set_addr_filter(&mut self, addrs: RangeInclusive<u16>) {
+        self.addr_filter = Some(addrs);
+    }
+
+    pub fn set_frame_filter(&mut self, frames: RangeInclusive<u64>) {
+        self.frame_filter = Some(frames);
+    }
+
+    pub fn start_instruction(&mut self, chip8: &Chip8) {
+        let state = chip8.get_state();
+        self.pc = state.pc;
+        self.opcode = state
+            .ram
+            .get(state.pc, 2)
+            .map(|op| u16::from_be_bytes([op[0], op[1]]))
+            .unwrap_or_default();
+        self.v = state.v;
+    }
+
+    pub fn end_instruction(&mut self, chip8: &Chip8) -> Result<()> {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self
+            .addr_filter
+            .as_ref()
+            .is_some_and(|addrs| !addrs.contains(&self.pc))
+            || self
+                .frame_filter
+                .as_ref()
+                .is_some_and(|frames| !frames.contains(&self.frame))
+        {
+            return Ok(());
+        }
+        let state = chip8.get_state();
+        let record = TraceRecord {
+            cycle,
+            frame: self.frame,
+            pc: self.pc,
+            opcode: self.opcode,
+            v_changes: (0..0x10)
+                .filter(|&reg| state.v[reg as usize] != self.v[reg as usize])
+                .map(|reg| (reg, state.v[reg as usize]))
+                .collect(),
+            i: state.i,
+            sp: state.sp,
+            delay: state.delay,
+            sound: state.sound,
+        };
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{record}")?,
+            TraceFormat::Binary => {
+                let mut writer = StateWriter::default();
+                record.write(&mut writer);
+                self.out.write_all(&writer.finish())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes the trace once per frame, so that it's complete whenever the emulator is closed
+    pub fn vblank(&mut self) -> Result<()> {
+        self.frame += 1;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub struct Trace {
+    pub records: Vec<TraceRecord>,
+}
+
+impl Trace {
+    // Reads back a binary trace
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = StateReader::with_magic(data, TRACE_MAGIC, TRACE_VERSION)?;
+        let mut records = Vec::new();
+        while !reader.is_at_end() {
+            records.push(TraceRecord::read(&mut reader)?);
+        }
+        Ok(Self { records })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{Platform, System};
+    use std::sync::{Arc, Mutex};
+
+    // Output kept by the test while the tracer writes to it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut chip8 = Chip8::init(Platform::Chip8, Platform::Chip8.default_quirks(), Some(0));
+        // V0 = 5, V0 += 3, I = 0x123, delay = V0, then loops on the jump
+        chip8
+            .load_program(&[0x60, 0x05, 0x70, 0x03, 0xA1, 0x23, 0xF0, 0x15, 0x12, 0x08])
+            .unwrap();
+        let (binary, text) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut tracers = [
+            Tracer::new(binary.clone(), TraceFormat::Binary).unwrap(),
+            Tracer::new(text.clone(), TraceFormat::Text).unwrap(),
+        ];
+        for _ in 0..2 {
+            for _ in 0..3 {
+                for tracer in &mut tracers {
+                    tracer.start_instruction(&chip8);
+                }
+                chip8.exec_instruction().unwrap();
+                for tracer in &mut tracers {
+                    tracer.end_instruction(&chip8).unwrap();
+                }
+            }
+            chip8.vblank();
+            for tracer in &mut tracers {
+                tracer.vblank().unwrap();
+            }
+        }
+
+        let trace = Trace::parse(&binary.0.lock().unwrap()).unwrap();
+        let sp = chip8.get_state().sp;
+        let record = |cycle, frame, pc, opcode, v_changes: &[(u8, u8)], i, delay| TraceRecord {
+            cycle,
+            frame,
+            pc,
+            opcode,
+            v_changes: v_changes.to_vec(),
+            i,
+            sp,
+            delay,
+            sound: 0,
+        };
+        assert_eq!(
+            trace.records,
+            [
+                record(0, 0, 0x200, 0x6005, &[(0, 5)], 0, 0),
+                record(1, 0, 0x202, 0x7003, &[(0, 8)], 0, 0),
+                record(2, 0, 0x204, 0xA123, &[], 0x123, 0),
+                record(3, 1, 0x206, 0xF015, &[], 0x123, 8),
+                record(4, 1, 0x208, 0x1208, &[], 0x123, 8),
+                record(5, 1, 0x208, 0x1208, &[], 0x123, 8),
+            ]
+        );
+        // Both formats hold the same records
+        let lines: Vec<String> = trace
+            .records
+            .iter()
+            .map(|record| record.to_string())
+            .collect();
+        let text = String::from_utf8(text.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(lines, text.lines().collect::<Vec<_>>());
+
+        assert!(Trace::parse(&binary.0.lock().unwrap()[..20]).is_err());
+        assert!(Trace::parse(b"RC8X").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// Runs test programs headless, then compares their display with the images stored in
+// `tests/expected`. Setting BLESS writes the images instead, to be checked by eye before being
+// committed.
+//
+// The programs in `tests/programs` are compiled from their Octo source, while Timendus' CHIP-8 test
+// suite (https://github.com/Timendus/chip8-test-suite) is run from the ROM vendored in `tests/roms`,
+// its images having been checked against the results documented by the suite.
+
+use {
+    rusty_chip8::{
+        keypad::Keypad,
+        mem::Memory16Bit,
+        octo::compile,
+        systems::{CHIP8_FONT_START, Chip8, MemoryIncrement, Platform, Register, System},
+        testrom::{Framebuffer, run_headless},
+    },
+    std::{path::PathBuf, process::Command},
+};
+
+const FRAMES: u32 = 60;
+const PLATFORMS: [Platform; 3] = [Platform::Chip8, Platform::SChip, Platform::XoChip];
+
+// Addresses Timendus' suite reads to skip its menus : the test to run, then e.g. the platform to
+// check the quirks of
+const TIMENDUS_TEST_ADDR: u16 = 0x1FF;
+const TIMENDUS_CHOICE_ADDR: u16 = 0x1FE;
+
+fn test_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(path)
+}
+
+fn compile_program(name: &str, platform: Platform) -> Vec<u8> {
+    let path = test_path(&format!("programs/{name}.8o"));
+    let source = std::fs::read_to_string(&path).expect("The program can be read");
+    compile(&source, platform).expect("The program compiles")
+}
+
+// Timendus' suite, set up to run one of its tests then pick `choice` in its menu, if any
+fn start_timendus(test: u8, choice: u8, platform: Platform) -> Chip8 {
+    let rom = std::fs::read(test_path("roms/chip8-test-suite.ch8")).expect("The ROM can be read");
+    let mut chip8 = start(&rom, platform);
+    let mem = chip8.get_mem_mut();
+    mem.set_byte(TIMENDUS_TEST_ADDR, test)
+        .expect("The address is in RAM");
+    mem.set_byte(TIMENDUS_CHOICE_ADDR, choice)
+        .expect("The address is in RAM");
+    chip8
+}
+
+fn start(program: &[u8], platform: Platform) -> Chip8 {
+    let mut chip8 = Chip8::init(platform, platform.default_quirks(), Some(0));
+    chip8.load_program(program).expect("The program loads");
+    chip8
+}
+
+fn run(chip8: &mut Chip8, frames: u32) {
+    let cycles_per_frame = chip8.get_platform().default_cycles_per_frame();
+    run_headless(chip8, frames, cycles_per_frame).expect("The program runs without faults");
+}
+
+fn check_display(chip8: &Chip8, expected: &str) {
+    let framebuffer = Framebuffer::capture(chip8);
+    let path = test_path(&format!("expected/{expected}.pbm"));
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::write(&path, framebuffer.to_pbm()).expect("The expected image can be written");
+        return;
+    }
+    let expected_framebuffer = match std::fs::read(&path) {
+        Ok(data) => Framebuffer::parse_pbm(&data).expect("The expected image is valid"),
+        Err(err) => panic!(
+            "{} : {}, run with BLESS=1 to write this display :\n{}",
+            path.display(),
+            err,
+            framebuffer
+        ),
+    };
+    let diff = framebuffer.diff(&expected_framebuffer);
+    assert_eq!(
+        diff,
+        0,
+        "{} pixels differ from {} :\n{}",
+        diff,
+        path.display(),
+        framebuffer
+    );
+}
+
+#[test]
+fn font() {
+    for platform in PLATFORMS {
+        let mut chip8 = start(&compile_program("font", platform), platform);
+        run(&mut chip8, FRAMES);
+        // I is left on the last digit drawn
+        assert_eq!(chip8.get_register(Register::V(0)), 16);
+        assert_eq!(chip8.get_register(Register::I), CHIP8_FONT_START + 0xF * 5);
+        check_display(&chip8, "font");
+    }
+}
+
+#[test]
+fn flags() {
+    for platform in PLATFORMS {
+        let mut chip8 = start(&compile_program("flags", platform), platform);
+        run(&mut chip8, FRAMES);
+        // All 12 checks reported, none failing
+        assert_eq!(chip8.get_register(Register::V(0xC)), 0);
+        assert_eq!(chip8.get_register(Register::V(0xA)), 32);
+        assert_eq!(chip8.get_register(Register::V(0xB)), 7);
+        check_display(&chip8, "flags");
+    }
+}
+
+#[test]
+fn quirks() {
+    for (platform, expected) in [
+        (Platform::Chip8, "quirks-chip8"),
+        (Platform::SChip, "quirks-schip"),
+        (Platform::XoChip, "quirks-xochip"),
+    ] {
+        let mut chip8 = start(&compile_program("quirks", platform), platform);
+        run(&mut chip8, FRAMES);
+        // The digits drawn are kept in VA to VE, I only moving past v0 when incremented by X + 1
+        let quirks = platform.default_quirks();
+        let increments = quirks.memory_increment == MemoryIncrement::XPlusOne;
+        for (reg, quirk) in [
+            (0xA, quirks.shift),
+            (0xB, quirks.vf_reset),
+            (0xC, increments),
+            (0xD, quirks.jump_vx),
+            (0xE, quirks.clipping),
+        ] {
+            assert_eq!(
+                chip8.get_register(Register::V(reg)),
+                quirk as u16,
+                "V{reg:X} on {platform:?}"
+            );
+        }
+        check_display(&chip8, expected);
+    }
+}
+
+#[test]
+fn keypad() {
+    let mut chip8 = start(&compile_program("keypad", Platform::Chip8), Platform::Chip8);
+    run(&mut chip8, 10);
+    // The key is only taken once released
+    let mut keypad = Keypad::default();
+    keypad.set(0xA, true);
+    chip8.set_keypad(keypad);
+    run(&mut chip8, 10);
+    chip8.set_keypad(Keypad::default());
+    run(&mut chip8, 10);
+    assert_eq!(chip8.get_register(Register::V(0)), 0xA);
+    check_display(&chip8, "keypad");
+}
+
+#[test]
+fn command_line() {
+    let run = |program: &str, option: &str, image: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_rusty-chip8"))
+            .args(["--test-rom", "--frames", &FRAMES.to_string(), option])
+            .arg(image)
+            .arg(test_path(&format!("programs/{program}.8o")))
+            .output()
+            .expect("The emulator runs")
+    };
+    let image = std::env::temp_dir().join(format!("rusty-chip8-test-{}.pbm", std::process::id()));
+    assert!(run("font", "--dump", &image).status.success());
+    assert!(run("font", "--expect", &image).status.success());
+    assert_eq!(run("flags", "--expect", &image).status.code(), Some(1));
+    std::fs::remove_file(image).expect("The image can be removed");
+}
+
+#[test]
+fn timendus_ibm_logo() {
+    let mut chip8 = start_timendus(1, 0, Platform::Chip8);
+    run(&mut chip8, FRAMES);
+    check_display(&chip8, "timendus-ibm-logo");
+}
+
+#[test]
+fn timendus_corax_plus() {
+    let mut chip8 = start_timendus(2, 0, Platform::Chip8);
+    run(&mut chip8, FRAMES);
+    check_display(&chip8, "timendus-corax+");
+}
+
+#[test]
+fn timendus_flags() {
+    let mut chip8 = start_timendus(3, 0, Platform::Chip8);
+    run(&mut chip8, 10 * FRAMES);
+    check_display(&chip8, "timendus-flags");
+}
+
+#[test]
+fn timendus_quirks() {
+    // The test measures the display wait over a few seconds
+    for (platform, choice, expected) in [
+        (Platform::Chip8, 1, "timendus-quirks-chip8"),
+        (Platform::SChip, 2, "timendus-quirks-schip"),
+        (Platform::XoChip, 3, "timendus-quirks-xochip"),
+    ] {
+        let mut chip8 = start_timendus(4, choice, platform);
+        run(&mut chip8, 10 * FRAMES);
+        check_display(&chip8, expected);
+    }
+}
+
+#[test]
+fn timendus_keypad() {
+    // The FX0A test, which wants a key pressed then released
+    let mut chip8 = start_timendus(5, 3, Platform::Chip8);
+    run(&mut chip8, FRAMES);
+    let mut keypad = Keypad::default();
+    keypad.set(0x5, true);
+    chip8.set_keypad(keypad);
+    run(&mut chip8, 10);
+    chip8.set_keypad(Keypad::default());
+    run(&mut chip8, FRAMES);
+    check_display(&chip8, "timendus-keypad");
+}